@@ -0,0 +1,22 @@
+//! SNES colors, which are 15 bits with 5 bits per channel, blue in the high bits and red in the
+//! low bits.
+
+use iced::Color;
+
+pub fn from_rgb8([r, g, b]: [u8; 3]) -> u16 {
+    (r as u16 >> 3) | (g as u16 >> 3) << 5 | (b as u16 >> 3) << 10
+}
+
+/// Channels are widened by repeating their high bits, so that 31 becomes 255.
+pub fn to_rgb8(color: u16) -> [u8; 3] {
+    let widen = |channel: u16| {
+        let channel = (channel & 0x1F) as u8;
+        channel << 3 | channel >> 2
+    };
+    [widen(color), widen(color >> 5), widen(color >> 10)]
+}
+
+pub fn to_color(color: u16) -> Color {
+    let [r, g, b] = to_rgb8(color);
+    Color::from_rgb8(r, g, b)
+}
@@ -0,0 +1,141 @@
+//! The block library, where tiles from the GFX slots are painted together to try out how they look
+//! side by side.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    graphics_file::{GraphicsFile, GraphicsSlot},
+    tilemap::TileInstance,
+};
+
+/// A tile painted into the block library. Its tile instance's id is relative to the start of its
+/// slot, so that it keeps pointing at the same graphics whatever order files finished loading in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockLibraryTile {
+    pub slot: GraphicsSlot,
+    #[serde(flatten)]
+    pub tile_instance: TileInstance,
+}
+impl BlockLibraryTile {
+    /// The tile instance to draw, pointing at its graphics in the all-bytes array, or None if its
+    /// slot isn't bound or the bound file doesn't have the tile.
+    pub fn resolve(
+        &self,
+        graphics_slots: &HashMap<GraphicsSlot, usize>,
+        graphics_files: &[GraphicsFile],
+    ) -> Option<TileInstance> {
+        let file = &graphics_files[*graphics_slots.get(&self.slot)?];
+        if self.tile_instance.id >= file.tile_count() {
+            return None;
+        }
+        let mut tile_instance = self.tile_instance;
+        tile_instance.id += file.first_tile_id();
+        tile_instance.set_bit_depth(file.bit_depth);
+        Some(tile_instance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        graphics_file::GraphicsFileSource,
+        lz,
+        tilemap::{BitDepth, TileCoords},
+    };
+
+    fn graphics_file(offset_in_all_bytes: usize, tile_count: usize) -> GraphicsFile {
+        GraphicsFile {
+            id: offset_in_all_bytes as u64,
+            source: GraphicsFileSource::Unsaved("test.bin".to_string()),
+            bytes: Arc::new(vec![0; tile_count * BitDepth::Bpp4.bytes_per_tile()]),
+            offset_in_all_bytes,
+            bit_depth: BitDepth::Bpp4,
+            compression: lz::Compression::None,
+            dirty: false,
+            size_conflict: None,
+        }
+    }
+
+    fn painted_tile(slot: GraphicsSlot, id: u32) -> BlockLibraryTile {
+        let mut tile_instance = TileInstance {
+            x: 8,
+            y: 16,
+            id,
+            pal: 2,
+            scale: 1,
+            flags: 0,
+        };
+        tile_instance.set_bit_depth(BitDepth::Bpp2);
+        BlockLibraryTile {
+            slot,
+            tile_instance,
+        }
+    }
+
+    /// A 128-tile file at the start of the all-bytes array, then a 16-tile one after it.
+    fn graphics_files() -> Vec<GraphicsFile> {
+        vec![
+            graphics_file(0, 128),
+            graphics_file(128 * BitDepth::Bpp4.bytes_per_tile(), 16),
+        ]
+    }
+
+    #[test]
+    fn resolves_to_the_bound_files_tiles() {
+        let graphics_slots = HashMap::from([(GraphicsSlot::Fg1, 0), (GraphicsSlot::Bg1, 1)]);
+        let resolved = painted_tile(GraphicsSlot::Bg1, 5)
+            .resolve(&graphics_slots, &graphics_files())
+            .unwrap();
+        assert_eq!(resolved.id, 128 + 5);
+        // The tile is drawn with the bit depth of the file it now points into.
+        assert_eq!(resolved.get_bit_depth(), BitDepth::Bpp4);
+        assert_eq!(resolved.get_tile_coords(), TileCoords(1, 2));
+    }
+
+    #[test]
+    fn slot_without_a_file_resolves_to_nothing() {
+        let graphics_slots = HashMap::from([(GraphicsSlot::Fg1, 0)]);
+        assert_eq!(
+            painted_tile(GraphicsSlot::Sp1, 5).resolve(&graphics_slots, &graphics_files()),
+            None
+        );
+    }
+
+    #[test]
+    fn follows_its_slot_to_another_file() {
+        let tile = painted_tile(GraphicsSlot::Fg2, 3);
+        let graphics_files = graphics_files();
+        let resolved_in = |file_index| {
+            tile.resolve(
+                &HashMap::from([(GraphicsSlot::Fg2, file_index)]),
+                &graphics_files,
+            )
+            .unwrap()
+            .id
+        };
+        assert_eq!(resolved_in(0), 3);
+        assert_eq!(resolved_in(1), 128 + 3);
+    }
+
+    #[test]
+    fn tile_past_the_end_of_a_shorter_file_resolves_to_nothing() {
+        let tile = painted_tile(GraphicsSlot::Fg1, 100);
+        let graphics_files = graphics_files();
+        assert!(tile
+            .resolve(&HashMap::from([(GraphicsSlot::Fg1, 0)]), &graphics_files)
+            .is_some());
+        assert_eq!(
+            tile.resolve(&HashMap::from([(GraphicsSlot::Fg1, 1)]), &graphics_files),
+            None
+        );
+        // The last tile the file has still resolves.
+        assert!(painted_tile(GraphicsSlot::Fg1, 15)
+            .resolve(&HashMap::from([(GraphicsSlot::Fg1, 1)]), &graphics_files)
+            .is_some());
+    }
+}
@@ -0,0 +1,376 @@
+//! An editor for a single palette color, with RGB and HSV sliders and a field for the SNES value.
+//! Colors are always snapped to the 5 bits per channel the SNES has, so what's shown is what the
+//! game will show.
+
+use iced::{Color, Element, Length};
+
+use crate::bgr555;
+
+/// These are messages that parent is expected to want to handle.
+#[derive(Debug, Clone, Copy)]
+pub enum PublicMessage {
+    /// Raised whenever the color changes, including while a slider is dragged, so that the parent
+    /// can write it into the palette. Carries the color's index in the palette and its new value.
+    ColorEdited(usize, u16),
+}
+
+/// Parent of this component should pass this Envelope to the Component::update function, which may return a PublicMessage.
+#[derive(Debug, Clone)]
+pub struct Envelope(PrivateMessage);
+
+#[derive(Debug, Clone)]
+enum PrivateMessage {
+    RgbChannelChanged(usize, u8),
+    HsvChannelChanged(usize, f32),
+    SliderReleased,
+    SnesValueInput(String),
+    SnesValueSubmitted,
+    UndoPressed,
+    ClosePressed,
+}
+
+pub struct Component {
+    /// The index in the palette of the color being edited, or None if the editor is closed.
+    color_index: Option<usize>,
+    color: u16,
+    /// Kept apart from `color` so that the HSV sliders don't jump around as the color gets snapped
+    /// to 5 bits per channel.
+    hsv: [f32; 3],
+    snes_value_input: String,
+    /// What the color was before the edit in progress, such as a slider drag, began.
+    color_before_edit: Option<u16>,
+    /// Each finished edit's color index, and the color from before it.
+    undo_stack: Vec<(usize, u16)>,
+}
+impl Component {
+    pub fn new() -> Self {
+        Self {
+            color_index: None,
+            color: 0,
+            hsv: [0.; 3],
+            snes_value_input: String::new(),
+            color_before_edit: None,
+            undo_stack: vec![],
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.color_index.is_some()
+    }
+
+    /// Starts editing the palette's color at the given index, which currently looks like `color`.
+    pub fn open(&mut self, color_index: usize, color: Color) {
+        self.finish_edit();
+        let [r, g, b, _] = color.into_rgba8();
+        self.color_index = Some(color_index);
+        self.show_color(bgr555::from_rgb8([r, g, b]));
+    }
+
+    pub fn update(&mut self, envelope: Envelope) -> Option<PublicMessage> {
+        let color_index = self.color_index?;
+        match envelope.0 {
+            PrivateMessage::RgbChannelChanged(channel, value) => {
+                let shift = channel * 5;
+                let color = self.color & !(0x1F << shift) | (value as u16) << shift;
+                self.edit(color_index, color)
+            }
+            PrivateMessage::HsvChannelChanged(channel, value) => {
+                let mut hsv = self.hsv;
+                hsv[channel] = value;
+                let [r, g, b] = hsv_to_rgb(hsv);
+                let color = bgr555::from_rgb8([r, g, b].map(|c| (c * 255.).round() as u8));
+                let message = self.edit(color_index, color);
+                self.hsv = hsv;
+                message
+            }
+            PrivateMessage::SliderReleased => {
+                self.finish_edit();
+                None
+            }
+            PrivateMessage::SnesValueInput(input) => {
+                self.snes_value_input = input;
+                None
+            }
+            PrivateMessage::SnesValueSubmitted => match parse_snes_value(&self.snes_value_input) {
+                Some(color) => {
+                    let message = self.edit(color_index, color);
+                    self.finish_edit();
+                    message
+                }
+                None => {
+                    println!("{:?} isn't a SNES color", self.snes_value_input);
+                    self.snes_value_input = format!("${:04X}", self.color);
+                    None
+                }
+            },
+            PrivateMessage::UndoPressed => {
+                self.finish_edit();
+                let (undone_color_index, color) = self.undo_stack.pop()?;
+                self.color_index = Some(undone_color_index);
+                self.show_color(color);
+                Some(PublicMessage::ColorEdited(undone_color_index, color))
+            }
+            PrivateMessage::ClosePressed => {
+                self.finish_edit();
+                self.color_index = None;
+                None
+            }
+        }
+    }
+
+    /// Changes the color as part of the edit in progress, starting one if there isn't one.
+    fn edit(&mut self, color_index: usize, color: u16) -> Option<PublicMessage> {
+        if color == self.color {
+            return None;
+        }
+        self.color_before_edit.get_or_insert(self.color);
+        self.show_color(color);
+        Some(PublicMessage::ColorEdited(color_index, color))
+    }
+
+    /// Ends the edit in progress, so that it can be undone in one go.
+    fn finish_edit(&mut self) {
+        if let (Some(color_index), Some(color_before_edit)) =
+            (self.color_index, self.color_before_edit.take())
+        {
+            if color_before_edit != self.color {
+                self.undo_stack.push((color_index, color_before_edit));
+            }
+        }
+    }
+
+    fn show_color(&mut self, color: u16) {
+        self.color = color;
+        self.hsv = rgb_to_hsv(bgr555::to_rgb8(color).map(|c| c as f32 / 255.));
+        self.snes_value_input = format!("${color:04X}");
+    }
+
+    pub fn view(&self) -> Element<'_, Envelope> {
+        use iced::widget::{column, row, *};
+
+        let Some(color_index) = self.color_index else {
+            return column![].into();
+        };
+        let swatch_color = bgr555::to_color(self.color);
+        let [r, g, b] = bgr555::to_rgb8(self.color);
+
+        let rgb_slider = |label, channel: usize| {
+            let value = (self.color >> (channel * 5) & 0x1F) as u8;
+            row![
+                text(label).width(16),
+                slider(0..=31, value, move |value| {
+                    Envelope(PrivateMessage::RgbChannelChanged(channel, value))
+                })
+                .on_release(Envelope(PrivateMessage::SliderReleased)),
+                text(value).width(24),
+            ]
+            .spacing(5)
+        };
+        // Hue is shown in degrees, and saturation and value as percentages.
+        let hsv_slider = |label, channel: usize, max: f32, step: f32, shown_scale: f32| {
+            let value = self.hsv[channel];
+            row![
+                text(label).width(16),
+                slider(0.0..=max, value, move |value| {
+                    Envelope(PrivateMessage::HsvChannelChanged(channel, value))
+                })
+                .step(step)
+                .on_release(Envelope(PrivateMessage::SliderReleased)),
+                text(format!("{:.0}", value * shown_scale)).width(24),
+            ]
+            .spacing(5)
+        };
+
+        column![
+            row![
+                container(Space::new(32, 32)).style(move |_| container::Style {
+                    background: Some(swatch_color.into()),
+                    ..Default::default()
+                }),
+                column![
+                    text(format!(
+                        "Line {:X}, color {:X}",
+                        color_index / 16,
+                        color_index % 16
+                    )),
+                    text(format!("RGB {r}, {g}, {b}")),
+                ],
+            ]
+            .spacing(10),
+            rgb_slider("R", 0),
+            rgb_slider("G", 1),
+            rgb_slider("B", 2),
+            hsv_slider("H", 0, 360., 1., 1.),
+            hsv_slider("S", 1, 1., 0.01, 100.),
+            hsv_slider("V", 2, 1., 0.01, 100.),
+            row![
+                text_input("$7FFF", &self.snes_value_input)
+                    .on_input(|input| Envelope(PrivateMessage::SnesValueInput(input)))
+                    .on_submit(Envelope(PrivateMessage::SnesValueSubmitted))
+                    .width(70),
+                button("Undo").on_press_maybe(
+                    (!self.undo_stack.is_empty() || self.color_before_edit.is_some())
+                        .then_some(Envelope(PrivateMessage::UndoPressed))
+                ),
+                button("Close").on_press(Envelope(PrivateMessage::ClosePressed)),
+            ]
+            .spacing(5),
+        ]
+        .spacing(5)
+        .width(Length::Fixed(220.))
+        .into()
+    }
+}
+
+/// Reads a SNES color written in hex, such as `$7FFF`, where the `$` is optional.
+fn parse_snes_value(input: &str) -> Option<u16> {
+    let input = input.trim();
+    let digits = input.strip_prefix('$').unwrap_or(input);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(digits, 16)
+        .ok()
+        .filter(|&color| color <= 0x7FFF)
+}
+
+/// Hue is in degrees, and everything else is from 0 to 1.
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let range = max - min;
+    let hue = if range == 0. {
+        0.
+    } else if max == r {
+        60. * ((g - b) / range).rem_euclid(6.)
+    } else if max == g {
+        60. * ((b - r) / range + 2.)
+    } else {
+        60. * ((r - g) / range + 4.)
+    };
+    let saturation = if max == 0. { 0. } else { range / max };
+    [hue, saturation, max]
+}
+
+fn hsv_to_rgb([hue, saturation, value]: [f32; 3]) -> [f32; 3] {
+    let chroma = value * saturation;
+    let hue_sector = (hue / 60.).rem_euclid(6.);
+    let x = chroma * (1. - (hue_sector % 2. - 1.).abs());
+    let [r, g, b] = match hue_sector as u32 {
+        0 => [chroma, x, 0.],
+        1 => [x, chroma, 0.],
+        2 => [0., chroma, x],
+        3 => [0., x, chroma],
+        4 => [x, 0., chroma],
+        _ => [chroma, 0., x],
+    };
+    let m = value - chroma;
+    [r + m, g + m, b + m]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opened_on(color_index: usize, color: u16) -> Component {
+        let mut component = Component::new();
+        component.open(color_index, bgr555::to_color(color));
+        component
+    }
+
+    fn submit(component: &mut Component, input: &str) -> Option<u16> {
+        component.update(Envelope(PrivateMessage::SnesValueInput(input.to_string())));
+        component
+            .update(Envelope(PrivateMessage::SnesValueSubmitted))
+            .map(|PublicMessage::ColorEdited(_, color)| color)
+    }
+
+    #[test]
+    fn converts_between_rgb_and_hsv() {
+        for rgb in [
+            [0., 0., 0.],
+            [1., 1., 1.],
+            [1., 0., 0.],
+            [0., 1., 0.],
+            [0., 0., 1.],
+            [1., 1., 0.],
+            [0.2, 0.4, 0.8],
+            [0.8, 0.1, 0.5],
+        ] {
+            let round_tripped = hsv_to_rgb(rgb_to_hsv(rgb));
+            for (channel, round_tripped_channel) in rgb.iter().zip(round_tripped) {
+                assert!(
+                    (channel - round_tripped_channel).abs() < 1e-5,
+                    "{rgb:?} came back as {round_tripped:?}"
+                );
+            }
+        }
+        assert_eq!(rgb_to_hsv([0., 0., 1.]), [240., 1., 1.]);
+        assert_eq!(rgb_to_hsv([0.5, 0.5, 0.5]), [0., 0., 0.5]);
+    }
+
+    #[test]
+    fn snaps_colors_to_5_bit_channels() {
+        // 0x7F is between 15 and 16 out of 31, and 5 bits keep the top of it.
+        let mut component = Component::new();
+        component.open(0x21, Color::from_rgb8(0x7F, 0xFF, 0x08));
+        assert_eq!(component.color, 15 | 31 << 5 | 1 << 10);
+
+        // Hue, saturation and value that land between SNES colors are snapped too.
+        let edited = component.update(Envelope(PrivateMessage::HsvChannelChanged(2, 0.5)));
+        let Some(PublicMessage::ColorEdited(0x21, color)) = edited else {
+            panic!("{edited:?} isn't an edit of color 0x21");
+        };
+        assert_eq!(bgr555::from_rgb8(bgr555::to_rgb8(color)), color);
+        assert_eq!(component.snes_value_input, format!("${color:04X}"));
+    }
+
+    #[test]
+    fn parses_snes_values() {
+        assert_eq!(parse_snes_value("$7FFF"), Some(0x7FFF));
+        assert_eq!(parse_snes_value("7fff"), Some(0x7FFF));
+        assert_eq!(parse_snes_value(" $001F "), Some(0x001F));
+        assert_eq!(parse_snes_value("$0"), Some(0));
+        assert_eq!(parse_snes_value("$8000"), None);
+        assert_eq!(parse_snes_value("FFFF"), None);
+        assert_eq!(parse_snes_value("$12G4"), None);
+        assert_eq!(parse_snes_value("+7FF"), None);
+        assert_eq!(parse_snes_value("$$7FF"), None);
+        assert_eq!(parse_snes_value("$"), None);
+        assert_eq!(parse_snes_value(""), None);
+    }
+
+    #[test]
+    fn submitting_a_bad_value_keeps_the_color() {
+        let mut component = opened_on(0x10, 0x1234);
+        assert_eq!(submit(&mut component, "$8000"), None);
+        assert_eq!(component.color, 0x1234);
+        assert_eq!(component.snes_value_input, "$1234");
+        assert_eq!(submit(&mut component, "7C00"), Some(0x7C00));
+    }
+
+    #[test]
+    fn undo_restores_the_color_from_before_each_edit() {
+        let mut component = opened_on(0x10, 0x1234);
+        assert_eq!(submit(&mut component, "$0000"), Some(0x0000));
+        // A slider drag is undone in one go.
+        for value in [3, 9, 20] {
+            component.update(Envelope(PrivateMessage::RgbChannelChanged(0, value)));
+        }
+        component.update(Envelope(PrivateMessage::SliderReleased));
+        assert_eq!(component.color, 20);
+
+        assert!(matches!(
+            component.update(Envelope(PrivateMessage::UndoPressed)),
+            Some(PublicMessage::ColorEdited(0x10, 0x0000))
+        ));
+        assert!(matches!(
+            component.update(Envelope(PrivateMessage::UndoPressed)),
+            Some(PublicMessage::ColorEdited(0x10, 0x1234))
+        ));
+        assert_eq!(component.snes_value_input, "$1234");
+        assert!(component
+            .update(Envelope(PrivateMessage::UndoPressed))
+            .is_none());
+    }
+}
@@ -0,0 +1,426 @@
+//! Converting full color images into 4bpp tiles that share a few palette lines, in the manner of
+//! superfamiconv. Colors are reduced to BGR555 before anything else, since that's all the SNES can
+//! show.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use crate::{
+    bgr555, planar,
+    tilemap::{BitDepth, TileInstance},
+};
+
+/// Color 0 of every line is transparent, leaving 15 for the tiles.
+pub const COLORS_PER_LINE: usize = 15;
+
+#[derive(Debug)]
+pub struct Conversion {
+    /// The image's distinct tiles, as 4bpp graphics.
+    pub graphics_bytes: Vec<u8>,
+    /// Colors 1 to 15 of each palette line, in BGR555. Lines with fewer colors leave the rest
+    /// unused.
+    pub palette_lines: Vec<Vec<u16>>,
+    /// A tile for each 8x8 area of the image, placed where the area was. Their `pal` is an index
+    /// into `palette_lines`.
+    pub tile_instances: Vec<TileInstance>,
+}
+
+impl Conversion {
+    pub fn tile_count(&self) -> u32 {
+        (self.graphics_bytes.len() / BitDepth::Bpp4.bytes_per_tile()) as u32
+    }
+}
+
+/// An 8x8 area of the image, in BGR555, with None for transparent pixels.
+struct ImageTile {
+    pixels: [Option<u16>; planar::PIXELS_PER_TILE],
+}
+impl ImageTile {
+    fn color_counts(&self) -> BTreeMap<u16, u32> {
+        let mut color_counts = BTreeMap::new();
+        for color in self.pixels.iter().flatten() {
+            *color_counts.entry(*color).or_default() += 1;
+        }
+        color_counts
+    }
+
+    /// The color index of each pixel if the tile used the given line, along with how far the
+    /// line's colors are from the tile's.
+    fn color_indices_in_line(&self, line: &[u16]) -> ([u8; planar::PIXELS_PER_TILE], u32) {
+        let mut color_indices = [0; planar::PIXELS_PER_TILE];
+        let mut total_squared_distance = 0;
+        for (color_index, pixel) in color_indices.iter_mut().zip(self.pixels) {
+            let Some(color) = pixel else {
+                continue;
+            };
+            let (nearest_index, squared_distance) = nearest_color(color, line);
+            *color_index = nearest_index as u8 + 1;
+            total_squared_distance += squared_distance;
+        }
+        (color_indices, total_squared_distance)
+    }
+}
+
+/// Converts an image into tiles and palette lines, using at most `max_palette_lines` lines. Tiles
+/// that come out the same are only stored once. Edges of the image that don't make a whole tile are
+/// left out.
+pub fn convert(image: &image::RgbaImage, max_palette_lines: usize) -> Conversion {
+    let width_in_tiles = image.width() as usize / planar::PIXELS_PER_TILE_ROW;
+    let height_in_tiles = image.height() as usize / planar::PIXELS_PER_TILE_ROW;
+    let mut tiles = (0..width_in_tiles * height_in_tiles)
+        .map(|tile_index| {
+            let mut pixels = [None; planar::PIXELS_PER_TILE];
+            for (pixel_index, pixel) in pixels.iter_mut().enumerate() {
+                let x = tile_index % width_in_tiles * planar::PIXELS_PER_TILE_ROW
+                    + pixel_index % planar::PIXELS_PER_TILE_ROW;
+                let y = tile_index / width_in_tiles * planar::PIXELS_PER_TILE_ROW
+                    + pixel_index / planar::PIXELS_PER_TILE_ROW;
+                let [r, g, b, a] = image.get_pixel(x as u32, y as u32).0;
+                *pixel = (a >= 0x80).then(|| bgr555::from_rgb8([r, g, b]));
+            }
+            ImageTile { pixels }
+        })
+        .collect::<Vec<_>>();
+
+    // A tile with more colors than fit in a line has to lose some before it can be given one.
+    for tile in tiles.iter_mut() {
+        let color_counts = tile.color_counts();
+        if color_counts.len() > COLORS_PER_LINE {
+            let reduced_colors = reduce_colors(&color_counts, COLORS_PER_LINE);
+            for color in tile.pixels.iter_mut().flatten() {
+                *color = reduced_colors[nearest_color(*color, &reduced_colors).0];
+            }
+        }
+    }
+
+    let mut palette_lines = build_palette_lines(&tiles, max_palette_lines.max(1));
+    if palette_lines.is_empty() {
+        // The image is entirely transparent.
+        palette_lines.push(vec![]);
+    }
+
+    let mut graphics_bytes = vec![];
+    let mut tile_ids = HashMap::new();
+    let mut tile_instances = vec![];
+    for (tile_index, tile) in tiles.iter().enumerate() {
+        let (pal, color_indices) = palette_lines
+            .iter()
+            .enumerate()
+            .map(|(pal, line)| (pal, tile.color_indices_in_line(line)))
+            .min_by_key(|(_, (_, total_squared_distance))| *total_squared_distance)
+            .map(|(pal, (color_indices, _))| (pal, color_indices))
+            .unwrap();
+        let tile_bytes = planar::encode_tile(&color_indices, BitDepth::Bpp4);
+        let next_id = tile_ids.len() as u32;
+        let id = *tile_ids.entry(tile_bytes.clone()).or_insert_with(|| {
+            graphics_bytes.extend(&tile_bytes);
+            next_id
+        });
+        let mut tile_instance = TileInstance {
+            x: (tile_index % width_in_tiles * planar::PIXELS_PER_TILE_ROW) as u32,
+            y: (tile_index / width_in_tiles * planar::PIXELS_PER_TILE_ROW) as u32,
+            id,
+            pal: pal as u8,
+            scale: 1,
+            flags: 0,
+        };
+        tile_instance.set_bit_depth(BitDepth::Bpp4);
+        tile_instances.push(tile_instance);
+    }
+
+    Conversion {
+        graphics_bytes,
+        palette_lines,
+        tile_instances,
+    }
+}
+
+/// Reads an image, then converts it.
+pub async fn load_and_convert(
+    path: PathBuf,
+    max_palette_lines: usize,
+) -> Option<(PathBuf, Arc<Conversion>)> {
+    let (path, image) = crate::png_sheet::load(path).await?;
+    let conversion = convert(&image, max_palette_lines);
+    Some((path, Arc::new(conversion)))
+}
+
+/// Packs the tiles' colors into at most `max_lines` lines. Each tile's colors go in whichever line
+/// they overlap with most, as long as they fit, and in a new line otherwise. Once there are no
+/// lines left, lines that end up with too many colors are reduced, leaving some tiles to make do
+/// with near colors.
+fn build_palette_lines(tiles: &[ImageTile], max_lines: usize) -> Vec<Vec<u16>> {
+    let mut color_counts = BTreeMap::new();
+    let mut color_sets = BTreeSet::new();
+    for tile in tiles {
+        let tile_color_counts = tile.color_counts();
+        for (color, count) in tile_color_counts.iter() {
+            *color_counts.entry(*color).or_default() += count;
+        }
+        if !tile_color_counts.is_empty() {
+            color_sets.insert(tile_color_counts.into_keys().collect::<BTreeSet<u16>>());
+        }
+    }
+    // Placing the tiles with the most colors first leaves the most room for the rest.
+    let mut color_sets = color_sets.into_iter().collect::<Vec<_>>();
+    color_sets.sort_by_key(|color_set| Reverse(color_set.len()));
+
+    let mut lines: Vec<BTreeSet<u16>> = vec![];
+    for color_set in color_sets {
+        let best_line = lines
+            .iter()
+            .enumerate()
+            .map(|(line_index, line)| (line_index, line.union(&color_set).count()))
+            .min_by_key(|(_, merged_len)| *merged_len);
+        match best_line {
+            Some((line_index, merged_len))
+                if merged_len <= COLORS_PER_LINE || lines.len() >= max_lines =>
+            {
+                lines[line_index].extend(color_set)
+            }
+            _ => lines.push(color_set),
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|line| {
+            if line.len() <= COLORS_PER_LINE {
+                line.into_iter().collect()
+            } else {
+                let line_color_counts = line
+                    .iter()
+                    .map(|color| (*color, color_counts[color]))
+                    .collect();
+                reduce_colors(&line_color_counts, COLORS_PER_LINE)
+            }
+        })
+        .collect()
+}
+
+fn channels(color: u16) -> [u8; 3] {
+    [
+        (color & 0x1F) as u8,
+        (color >> 5 & 0x1F) as u8,
+        (color >> 10 & 0x1F) as u8,
+    ]
+}
+
+fn squared_distance(color: u16, other_color: u16) -> u32 {
+    channels(color)
+        .iter()
+        .zip(channels(other_color))
+        .map(|(a, b)| (*a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+/// The index of the nearest of the colors, and the squared distance to it.
+fn nearest_color(color: u16, colors: &[u16]) -> (usize, u32) {
+    colors
+        .iter()
+        .map(|other_color| squared_distance(color, *other_color))
+        .enumerate()
+        .min_by_key(|(_, squared_distance)| *squared_distance)
+        .unwrap_or((0, 0))
+}
+
+/// Median cut: repeatedly splits the box of colors with the widest channel in two at the median
+/// of that channel, weighted by how many pixels have each color, until there are `max_colors`
+/// boxes. Each box then becomes the weighted average of its colors.
+fn reduce_colors(color_counts: &BTreeMap<u16, u32>, max_colors: usize) -> Vec<u16> {
+    let mut boxes = vec![color_counts
+        .iter()
+        .map(|(color, count)| (channels(*color), *count))
+        .collect::<Vec<_>>()];
+    while boxes.len() < max_colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(box_index, colors)| {
+                (0..3).map(move |channel| {
+                    let values = colors.iter().map(|(channels, _)| channels[channel]);
+                    let range = values.clone().max().unwrap() - values.min().unwrap();
+                    (box_index, channel, range)
+                })
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let Some((box_index, channel, _)) = widest else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(box_index);
+        colors.sort_by_key(|(channels, _)| channels[channel]);
+        let total_count = colors.iter().map(|(_, count)| count).sum::<u32>();
+        let mut count_so_far = 0;
+        let median = colors
+            .iter()
+            .position(|(_, count)| {
+                count_so_far += count;
+                count_so_far * 2 >= total_count
+            })
+            .unwrap_or(0);
+        let upper_colors = colors.split_off((median + 1).min(colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(upper_colors);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let total_count = colors.iter().map(|(_, count)| count).sum::<u32>().max(1);
+            let [r, g, b] = [0, 1, 2].map(|channel| {
+                let weighted_sum = colors
+                    .iter()
+                    .map(|(channels, count)| channels[channel] as u32 * count)
+                    .sum::<u32>();
+                ((weighted_sum + total_count / 2) / total_count) as u16
+            });
+            r | g << 5 | b << 10
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SNES color with the given 5-bit channels.
+    fn snes_color(r: u16, g: u16, b: u16) -> u16 {
+        r | g << 5 | b << 10
+    }
+
+    /// An image of tiles laid out left to right, each given as the color of each of its pixels,
+    /// with None for transparent ones.
+    fn image_of_tiles(tiles: &[[Option<u16>; planar::PIXELS_PER_TILE]]) -> image::RgbaImage {
+        image::RgbaImage::from_fn(tiles.len() as u32 * 8, 8, |x, y| {
+            let pixel = tiles[x as usize / 8][(y * 8 + x % 8) as usize];
+            match pixel {
+                Some(color) => {
+                    let [r, g, b] = bgr555::to_rgb8(color);
+                    image::Rgba([r, g, b, 0xFF])
+                }
+                None => image::Rgba([0, 0, 0, 0]),
+            }
+        })
+    }
+
+    /// A tile using the colors in turn, one per pixel.
+    fn tile_of_colors(colors: &[u16]) -> [Option<u16>; planar::PIXELS_PER_TILE] {
+        std::array::from_fn(|pixel| Some(colors[pixel % colors.len()]))
+    }
+
+    /// The colors the converted tile shows, going by its palette line.
+    fn converted_colors(conversion: &Conversion, tile_index: usize) -> Vec<Option<u16>> {
+        let tile_instance = conversion.tile_instances[tile_index];
+        let tile_bytes = &conversion.graphics_bytes[tile_instance.id as usize * 32..][..32];
+        let line = &conversion.palette_lines[tile_instance.pal as usize];
+        planar::decode_tile(tile_bytes, BitDepth::Bpp4)
+            .iter()
+            .map(|color_index| match color_index {
+                0 => None,
+                color_index => Some(line[*color_index as usize - 1]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gives_a_15_color_tile_one_exact_line() {
+        let colors = (0..15)
+            .map(|i| snes_color(i * 2, 31 - i, i))
+            .collect::<Vec<_>>();
+        let tile = tile_of_colors(&colors);
+        let conversion = convert(&image_of_tiles(&[tile]), 4);
+        assert_eq!(conversion.palette_lines.len(), 1);
+        assert_eq!(
+            conversion.palette_lines[0].iter().collect::<BTreeSet<_>>(),
+            colors.iter().collect::<BTreeSet<_>>()
+        );
+        assert_eq!(converted_colors(&conversion, 0), tile);
+    }
+
+    #[test]
+    fn shares_lines_between_tiles_whose_colors_fit() {
+        let reds = (0..8).map(|i| snes_color(i * 4, 0, 0)).collect::<Vec<_>>();
+        let blues = (0..7)
+            .map(|i| snes_color(0, 0, i * 4 + 1))
+            .collect::<Vec<_>>();
+        let conversion = convert(
+            &image_of_tiles(&[tile_of_colors(&reds), tile_of_colors(&blues)]),
+            4,
+        );
+        assert_eq!(conversion.palette_lines.len(), 1);
+        assert_eq!(converted_colors(&conversion, 1), tile_of_colors(&blues));
+    }
+
+    #[test]
+    fn reduces_colors_when_tiles_need_more_lines_than_allowed() {
+        let tiles = (0..3)
+            .map(|tile| {
+                let colors = (0..15)
+                    .map(|i| snes_color(i * 2, tile * 10, 31 - i))
+                    .collect::<Vec<_>>();
+                tile_of_colors(&colors)
+            })
+            .collect::<Vec<_>>();
+        let conversion = convert(&image_of_tiles(&tiles), 2);
+        assert_eq!(conversion.palette_lines.len(), 2);
+        assert!(conversion
+            .palette_lines
+            .iter()
+            .all(|line| line.len() <= COLORS_PER_LINE));
+        // With 45 colors and room for 30, some tiles show near colors rather than their own.
+        assert!(
+            (0..3).any(|tile_index| converted_colors(&conversion, tile_index) != tiles[tile_index])
+        );
+        // But no pixel turns transparent.
+        assert!(
+            (0..3).all(|tile_index| converted_colors(&conversion, tile_index)
+                .iter()
+                .all(Option::is_some))
+        );
+    }
+
+    #[test]
+    fn reduces_tiles_with_more_than_15_colors() {
+        let colors = (0..64)
+            .map(|i| snes_color(i % 32, i / 2, 0))
+            .collect::<Vec<_>>();
+        let conversion = convert(&image_of_tiles(&[tile_of_colors(&colors)]), 1);
+        assert_eq!(conversion.palette_lines.len(), 1);
+        assert!(conversion.palette_lines[0].len() <= COLORS_PER_LINE);
+    }
+
+    #[test]
+    fn stores_duplicate_tiles_once() {
+        let tile = tile_of_colors(&[snes_color(31, 0, 0), snes_color(0, 31, 0)]);
+        let other_tile = tile_of_colors(&[snes_color(0, 0, 31)]);
+        let conversion = convert(&image_of_tiles(&[tile, other_tile, tile]), 1);
+        assert_eq!(conversion.tile_count(), 2);
+        let ids = conversion
+            .tile_instances
+            .iter()
+            .map(|tile_instance| tile_instance.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [0, 1, 0]);
+        assert_eq!(
+            conversion.tile_instances[2].get_tile_coords(),
+            crate::tilemap::TileCoords(2, 0)
+        );
+    }
+
+    #[test]
+    fn converts_fully_transparent_images() {
+        let conversion = convert(&image_of_tiles(&[[None; planar::PIXELS_PER_TILE]; 2]), 3);
+        assert_eq!(conversion.palette_lines, [Vec::<u16>::new()]);
+        assert_eq!(conversion.tile_count(), 1);
+        assert_eq!(conversion.graphics_bytes, [0; 32]);
+        assert_eq!(conversion.tile_instances.len(), 2);
+    }
+}
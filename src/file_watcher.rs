@@ -0,0 +1,62 @@
+//! Watching the files open in the editor, so that changes other programs make to them show up
+//! without a restart.
+
+use std::path::{Path, PathBuf};
+
+use iced::{
+    futures::{channel::mpsc, SinkExt, StreamExt},
+    stream, Subscription,
+};
+use notify::{RecursiveMode, Watcher};
+
+/// Raises the path of any of the given files when it changes on disk. Their directories are watched
+/// rather than the files themselves, since many programs save by replacing a file instead of
+/// writing into it. A program may raise several changes for one save.
+pub fn watch(paths: Vec<PathBuf>) -> Subscription<PathBuf> {
+    // The watcher is recreated whenever the set of paths changes.
+    Subscription::run_with_id(
+        paths.clone(),
+        stream::channel(16, move |mut output| async move {
+            let (sender, mut receiver) = mpsc::unbounded();
+            let watcher = notify::recommended_watcher(move |event| {
+                let _ = sender.unbounded_send(event);
+            });
+            let mut watcher = match watcher {
+                Ok(watcher) => watcher,
+                Err(error) => {
+                    println!("Couldn't watch files for changes: {error}");
+                    return;
+                }
+            };
+            let mut directories = paths
+                .iter()
+                .filter_map(|path| path.parent())
+                .collect::<Vec<&Path>>();
+            directories.sort();
+            directories.dedup();
+            for directory in directories {
+                if let Err(error) = watcher.watch(directory, RecursiveMode::NonRecursive) {
+                    println!("Couldn't watch {directory:?} for changes: {error}");
+                }
+            }
+
+            while let Some(event) = receiver.next().await {
+                let event: notify::Event = match event {
+                    Ok(event) => event,
+                    Err(error) => {
+                        println!("Error while watching files: {error}");
+                        continue;
+                    }
+                };
+                if !(event.kind.is_create() || event.kind.is_modify()) {
+                    continue;
+                }
+                for path in event.paths {
+                    if paths.contains(&path) && output.send(path).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }),
+    )
+}
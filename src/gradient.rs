@@ -0,0 +1,304 @@
+//! Shading ramps, made by filling the colors between anchor colors with colors interpolated from
+//! them.
+
+use std::sync::{Arc, RwLock};
+
+use iced::{Alignment, Element};
+
+use crate::{
+    bgr555,
+    palette::{srgb_to_linear, Palette},
+};
+
+/// These are messages that parent is expected to want to handle.
+#[derive(Debug, Clone)]
+pub enum PublicMessage {
+    /// Raised when the colors from `get_previewed_colors` change, so that the parent can show them
+    /// in the palette.
+    PreviewChanged,
+    /// Raised when the gradient is applied, so that the parent can write it into the palette.
+    /// Carries the palette index and BGR555 value of each color between the anchors.
+    GradientApplied(Vec<(usize, u16)>),
+}
+
+/// Parent of this component should pass this Envelope to the Component::update function, which may return a PublicMessage.
+#[derive(Debug, Clone)]
+pub struct Envelope(PrivateMessage);
+
+#[derive(Debug, Clone)]
+enum PrivateMessage {
+    ToggleAnchorPressed(usize),
+    ClearPressed,
+    InterpolationSelected(Interpolation),
+    PreviewToggled(bool),
+    ApplyPressed,
+}
+
+pub struct Component {
+    palette: Arc<RwLock<Palette>>,
+    /// The palette indices of the colors gradients are made between, all in the same line.
+    anchors: Vec<usize>,
+    interpolation: Interpolation,
+    /// Whether the gradient is shown in place of the colors it would replace.
+    previewed: bool,
+}
+impl Component {
+    pub fn new(palette: Arc<RwLock<Palette>>) -> Self {
+        Self {
+            palette,
+            anchors: vec![],
+            interpolation: Default::default(),
+            previewed: false,
+        }
+    }
+
+    pub fn clear_anchors(&mut self) {
+        self.anchors.clear();
+    }
+
+    /// The colors the gradient would give, by palette index, in BGR555, if it's previewed.
+    pub fn get_previewed_colors(&self) -> Vec<(usize, u16)> {
+        if self.previewed {
+            self.colors()
+        } else {
+            vec![]
+        }
+    }
+
+    pub fn update(&mut self, envelope: Envelope) -> Option<PublicMessage> {
+        match envelope.0 {
+            PrivateMessage::ToggleAnchorPressed(color_index) => {
+                if let Some(anchor_index) = self
+                    .anchors
+                    .iter()
+                    .position(|anchor| *anchor == color_index)
+                {
+                    self.anchors.remove(anchor_index);
+                } else {
+                    // Gradients stay within a line.
+                    self.anchors
+                        .retain(|anchor| anchor / 16 == color_index / 16);
+                    self.anchors.push(color_index);
+                    self.anchors.sort();
+                }
+            }
+            PrivateMessage::ClearPressed => self.anchors.clear(),
+            PrivateMessage::InterpolationSelected(interpolation) => {
+                self.interpolation = interpolation;
+            }
+            PrivateMessage::PreviewToggled(previewed) => self.previewed = previewed,
+            PrivateMessage::ApplyPressed => {
+                self.previewed = false;
+                return Some(PublicMessage::GradientApplied(self.colors()));
+            }
+        }
+        Some(PublicMessage::PreviewChanged)
+    }
+
+    /// Offers to toggle the selected color as an anchor.
+    pub fn view(&self, selected_color: Option<usize>) -> Element<'_, Envelope> {
+        use iced::widget::{row, *};
+
+        let anchors = if self.anchors.is_empty() {
+            "No anchors".to_string()
+        } else {
+            self.anchors
+                .iter()
+                .map(|anchor| format!("{:X}:{:X}", anchor / 16, anchor % 16))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        row![
+            text("Gradient"),
+            pick_list(
+                Interpolation::ALL,
+                Some(self.interpolation),
+                |interpolation| { Envelope(PrivateMessage::InterpolationSelected(interpolation)) }
+            ),
+            button("Toggle anchor").on_press_maybe(
+                selected_color
+                    .map(|color_index| Envelope(PrivateMessage::ToggleAnchorPressed(color_index)))
+            ),
+            text(anchors),
+            button("Clear").style(button::secondary).on_press_maybe(
+                (!self.anchors.is_empty()).then_some(Envelope(PrivateMessage::ClearPressed))
+            ),
+            checkbox("Preview", self.previewed)
+                .on_toggle(|previewed| Envelope(PrivateMessage::PreviewToggled(previewed))),
+            button("Apply").on_press_maybe(
+                (self.anchors.len() >= 2).then_some(Envelope(PrivateMessage::ApplyPressed))
+            ),
+        ]
+        .spacing(10)
+        .padding(10)
+        .align_y(Alignment::Center)
+        .into()
+    }
+
+    /// The colors the gradient between the anchors would give, by palette index, in BGR555.
+    fn colors(&self) -> Vec<(usize, u16)> {
+        let palette = self.palette.read().unwrap();
+        let anchors = self
+            .anchors
+            .iter()
+            .map(|&anchor| {
+                let [r, g, b, _] = palette[anchor].into_rgba8();
+                (anchor, bgr555::from_rgb8([r, g, b]))
+            })
+            .collect::<Vec<_>>();
+        fill(&anchors, self.interpolation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+    /// Mixes the amounts of light, so that ramps don't darken in the middle as sRGB mixing does.
+    LinearRgb,
+    /// Mixes in OKLab, where equal steps look equally far apart.
+    #[default]
+    Oklab,
+}
+impl Interpolation {
+    pub const ALL: [Interpolation; 2] = [Self::LinearRgb, Self::Oklab];
+
+    fn into_space(self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            Self::LinearRgb => rgb,
+            Self::Oklab => linear_rgb_to_oklab(rgb),
+        }
+    }
+
+    fn out_of_space(self, color: [f32; 3]) -> [f32; 3] {
+        match self {
+            Self::LinearRgb => color,
+            Self::Oklab => oklab_to_linear_rgb(color),
+        }
+    }
+}
+impl std::fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::LinearRgb => "Linear RGB",
+            Self::Oklab => "OKLab",
+        })
+    }
+}
+
+/// Gives the colors between each pair of neighboring anchors, which are palette indices along
+/// with their BGR555 colors, sorted by index. The colors are snapped to the nearest SNES colors.
+pub fn fill(anchors: &[(usize, u16)], interpolation: Interpolation) -> Vec<(usize, u16)> {
+    let to_space = |color: u16| {
+        interpolation.into_space(bgr555::to_rgb8(color).map(|c| srgb_to_linear(c as f32 / 255.)))
+    };
+    anchors
+        .windows(2)
+        .flat_map(|pair| {
+            let [(start_index, start_color), (end_index, end_color)] = [pair[0], pair[1]];
+            let (start, end) = (to_space(start_color), to_space(end_color));
+            (start_index + 1..end_index).map(move |index| {
+                let t = (index - start_index) as f32 / (end_index - start_index) as f32;
+                let mixed = [0, 1, 2].map(|channel| start[channel] * (1. - t) + end[channel] * t);
+                let [r, g, b] = interpolation
+                    .out_of_space(mixed)
+                    .map(|c| (linear_to_srgb(c.clamp(0., 1.)) * 31.).round() as u16);
+                (index, r | g << 5 | b << 10)
+            })
+        })
+        .collect()
+}
+
+fn linear_to_srgb(channel: f32) -> f32 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn linear_rgb_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+fn oklab_to_linear_rgb([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = (lightness + 0.39633778 * a + 0.21580376 * b).powi(3);
+    let m = (lightness - 0.105561346 * a - 0.06385417 * b).powi(3);
+    let s = (lightness - 0.08948418 * a - 1.2914855 * b).powi(3);
+    [
+        4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+        -1.268438 * l + 2.6097574 * m - 0.34131938 * s,
+        -0.0041960863 * l - 0.7034186 * m + 1.7076147 * s,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: u16 = 0x0000;
+    const WHITE: u16 = 0x7FFF;
+
+    fn channels(color: u16) -> [u16; 3] {
+        [color & 0x1F, color >> 5 & 0x1F, color >> 10 & 0x1F]
+    }
+
+    #[test]
+    fn fills_only_between_the_anchors() {
+        for interpolation in Interpolation::ALL {
+            let filled = fill(
+                &[(0, BLACK), (4, WHITE), (5, BLACK), (8, WHITE)],
+                interpolation,
+            );
+            let indices = filled.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+            assert_eq!(indices, [1, 2, 3, 6, 7]);
+            assert!(fill(&[(3, WHITE)], interpolation).is_empty());
+        }
+    }
+
+    #[test]
+    fn rounds_to_the_nearest_snes_color() {
+        // Half as much light as white is 0.735 in sRGB, which is 22.8 out of 31.
+        let filled = fill(&[(0, BLACK), (2, WHITE)], Interpolation::LinearRgb);
+        assert_eq!(filled, [(1, 23 | 23 << 5 | 23 << 10)]);
+    }
+
+    #[test]
+    fn keeps_grays_gray() {
+        for interpolation in Interpolation::ALL {
+            for (_, color) in fill(&[(0, BLACK), (15, WHITE)], interpolation) {
+                let [r, g, b] = channels(color);
+                assert!(r == g && g == b, "{color:04X} isn't gray");
+            }
+        }
+    }
+
+    #[test]
+    fn ramps_up_between_the_ends() {
+        for interpolation in Interpolation::ALL {
+            let filled = fill(&[(0, BLACK), (15, WHITE)], interpolation);
+            let reds = filled
+                .iter()
+                .map(|(_, color)| channels(*color)[0])
+                .collect::<Vec<_>>();
+            assert!(reds.windows(2).all(|pair| pair[0] < pair[1]), "{reds:?}");
+            assert!(reds.iter().all(|red| *red < 31), "{reds:?}");
+        }
+    }
+
+    #[test]
+    fn gives_back_a_color_mixed_with_itself() {
+        for interpolation in Interpolation::ALL {
+            for color in [0x001F, 0x03E0, 0x7C00, 0x1234] {
+                for (_, filled) in fill(&[(0, color), (3, color)], interpolation) {
+                    assert_eq!(filled, color, "{interpolation}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,442 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{lz, rom, tilemap, tilemap::BitDepth};
+
+/// Lets the user pick any number of graphics files to open.
+pub async fn pick_graphics_files() -> Vec<PathBuf> {
+    rfd::AsyncFileDialog::new()
+        .set_title("Open graphics")
+        .add_filter("Graphics files", &["bin"])
+        .add_filter("All files", &["*"])
+        .pick_files()
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|file_handle| file_handle.path().to_path_buf())
+        .collect()
+}
+
+pub async fn pick_graphics_file_to_save(file_name: String) -> Option<PathBuf> {
+    rfd::AsyncFileDialog::new()
+        .set_title("Save graphics")
+        .add_filter("Graphics files", &["bin"])
+        .set_file_name(file_name)
+        .save_file()
+        .await
+        .map(|file_handle| file_handle.path().to_path_buf())
+}
+
+/// Reads a graphics file, decompressing it if it's LZ2 or LZ3 compressed.
+pub async fn load_file(path: PathBuf) -> Option<(PathBuf, Arc<Vec<u8>>, lz::Compression)> {
+    match tokio::fs::read(&path).await {
+        Ok(contents) => {
+            let (compression, bytes) = lz::detect_and_decompress(contents);
+            Some((path, Arc::new(bytes), compression))
+        }
+        Err(error) => {
+            println!("Couldn't read {path:?}: {error}");
+            None
+        }
+    }
+}
+
+/// Writes a graphics file back to disk, compressed the same way it was when it was read. Whatever
+/// was there before is kept in a backup named after the time it was replaced. The new contents go
+/// to a temporary file first, which is then renamed over the old one, so that a failed save can't
+/// leave a half-written file behind. Returns the bytes that were saved.
+pub async fn save_file(
+    path: PathBuf,
+    bytes: Arc<Vec<u8>>,
+    compression: lz::Compression,
+) -> Option<Arc<Vec<u8>>> {
+    let contents = lz::compress(compression, &bytes);
+    let file_name = path.file_name()?.to_string_lossy().into_owned();
+
+    if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or_default();
+        let backup_path = path.with_file_name(format!("{file_name}.{timestamp}.bak"));
+        tokio::fs::copy(&path, &backup_path)
+            .await
+            .inspect_err(|error| println!("Couldn't back up {path:?} to {backup_path:?}: {error}"))
+            .ok()?;
+    }
+
+    let temporary_path = path.with_file_name(format!("{file_name}.tmp"));
+    let written = async {
+        tokio::fs::write(&temporary_path, contents).await?;
+        // Make sure the contents are on disk before they replace the old file.
+        tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&temporary_path)
+            .await?
+            .sync_all()
+            .await?;
+        tokio::fs::rename(&temporary_path, &path).await
+    }
+    .await;
+    if let Err(error) = written {
+        println!("Couldn't write {path:?}: {error}");
+        let _ = tokio::fs::remove_file(&temporary_path).await;
+        return None;
+    }
+    Some(bytes)
+}
+
+/// Opens an SMW ROM picked by the user, and decompresses all of its GFX files.
+pub async fn open_rom() -> Option<(PathBuf, Arc<Vec<rom::RomGraphicsFile>>)> {
+    let file_handle = rfd::AsyncFileDialog::new()
+        .set_title("Open SMW ROM")
+        .add_filter("SNES ROM", &["smc", "sfc"])
+        .pick_file()
+        .await?;
+    load_rom(file_handle.path().to_path_buf()).await
+}
+
+/// Reads an SMW ROM and decompresses all of its GFX files.
+pub async fn load_rom(path: PathBuf) -> Option<(PathBuf, Arc<Vec<rom::RomGraphicsFile>>)> {
+    let contents = tokio::fs::read(&path)
+        .await
+        .inspect_err(|error| println!("Couldn't read {path:?}: {error}"))
+        .ok()?;
+    let rom = match rom::Rom::new(contents) {
+        Ok(rom) => rom,
+        Err(error) => {
+            println!("Couldn't open {path:?}: {error}");
+            return None;
+        }
+    };
+    println!("Opened {path:?} as a {:?} ROM", rom.mapping());
+    let rom_graphics_files = rom
+        .read_graphics_files()
+        .into_iter()
+        .filter_map(|rom_graphics_file| {
+            rom_graphics_file
+                .inspect_err(|error| println!("Skipping graphics in {path:?}: {error}"))
+                .ok()
+        })
+        .collect();
+    Some((path, Arc::new(rom_graphics_files)))
+}
+
+/// Where a graphics file's bytes were loaded from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GraphicsFileSource {
+    /// A loose file, such as an ExGFX file.
+    File(PathBuf),
+    /// One of the GFX files in an SMW ROM, by its number.
+    Rom(PathBuf, usize),
+    /// A file made in the editor, such as an imported PNG, with the name it's suggested to be
+    /// saved as. It isn't written anywhere until the user picks where to save it.
+    Unsaved(String),
+}
+impl GraphicsFileSource {
+    /// An unsaved file for graphics made from the image at the given path, suggested to be saved
+    /// next to it.
+    pub fn unsaved_from_image(image_path: &Path) -> Self {
+        Self::Unsaved(image_path.with_extension("bin").file_name().map_or_else(
+            || "graphics.bin".into(),
+            |file_name| file_name.to_string_lossy().into_owned(),
+        ))
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::File(path) => path.file_name().unwrap().to_string_lossy().into_owned(),
+            Self::Rom(_, gfx_number) => format!("GFX{gfx_number:02X}"),
+            Self::Unsaved(file_name) => file_name.clone(),
+        }
+    }
+}
+
+/// The GFX slots of SMW's levels, as laid out in Lunar Magic's 8x8 tile editor. Each one holds 128
+/// tiles, and binding a file to a slot fixes which tile numbers its tiles get.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GraphicsSlot {
+    Fg1,
+    Fg2,
+    Bg1,
+    Fg3,
+    Bg2,
+    Fg4,
+    Bg3,
+    Sp1,
+    Sp2,
+    Sp3,
+    Sp4,
+}
+impl GraphicsSlot {
+    pub const ALL: [GraphicsSlot; 11] = [
+        Self::Fg1,
+        Self::Fg2,
+        Self::Bg1,
+        Self::Fg3,
+        Self::Bg2,
+        Self::Fg4,
+        Self::Bg3,
+        Self::Sp1,
+        Self::Sp2,
+        Self::Sp3,
+        Self::Sp4,
+    ];
+    pub const TILES_PER_SLOT: u32 = 128;
+
+    /// The tile number of the slot's first tile. Tiles 0x380-0x3FF are left for animations, as they
+    /// are in SMW.
+    pub fn first_tile_number(self) -> u32 {
+        match self {
+            Self::Fg1 => 0x000,
+            Self::Fg2 => 0x080,
+            Self::Bg1 => 0x100,
+            Self::Fg3 => 0x180,
+            Self::Bg2 => 0x200,
+            Self::Fg4 => 0x280,
+            Self::Bg3 => 0x300,
+            Self::Sp1 => 0x400,
+            Self::Sp2 => 0x480,
+            Self::Sp3 => 0x500,
+            Self::Sp4 => 0x580,
+        }
+    }
+}
+impl std::fmt::Display for GraphicsSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Fg1 => "FG1",
+            Self::Fg2 => "FG2",
+            Self::Bg1 => "BG1",
+            Self::Fg3 => "FG3",
+            Self::Bg2 => "BG2",
+            Self::Fg4 => "FG4",
+            Self::Bg3 => "BG3",
+            Self::Sp1 => "SP1",
+            Self::Sp2 => "SP2",
+            Self::Sp3 => "SP3",
+            Self::Sp4 => "SP4",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// What to do when a graphics file's contents on disk change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOnDisk {
+    /// The file on disk holds what's already shown, such as when the editor saved it itself. It no
+    /// longer has unsaved edits.
+    AlreadyShown,
+    /// The file changed size on disk, to the given size, so it no longer fits in its place in the
+    /// all-bytes array.
+    SizeConflict(usize),
+    /// The file has unsaved edits, which are kept rather than replaced.
+    KeepEdits,
+    /// The new contents can be put in the file's place.
+    Reload,
+}
+
+pub struct GraphicsFile {
+    /// Identifies the file for as long as the app runs, unlike its index, which another file gets
+    /// once this one is closed.
+    pub id: u64,
+    pub source: GraphicsFileSource,
+    pub bytes: Arc<Vec<u8>>,
+    pub offset_in_all_bytes: usize,
+    pub bit_depth: BitDepth,
+    /// How the file is compressed on disk, so that it can be saved back the same way.
+    pub compression: lz::Compression,
+    /// Set when the file's bytes in the all-bytes array have been edited since they were read or
+    /// saved.
+    pub dirty: bool,
+    /// Set to the file's size on disk when it changed size there. It can't be reloaded then, since
+    /// it no longer fits in its place in the all-bytes array.
+    pub size_conflict: Option<usize>,
+}
+impl GraphicsFile {
+    /// Files are placed in the all-bytes array at multiples of this, the least common multiple of
+    /// the tile sizes of every bit depth (16, 24, 32 and 64 bytes). That way the first tile id of a
+    /// file is a whole number whichever bit depth it's displayed with.
+    pub const ALIGNMENT_IN_ALL_BYTES: usize = 192;
+
+    /// SMW's own GFX files are 3bpp and hold 128 tiles, anything else is most likely 4bpp.
+    pub fn guess_bit_depth(len: usize) -> BitDepth {
+        if len == 128 * BitDepth::Bpp3.bytes_per_tile() {
+            BitDepth::Bpp3
+        } else {
+            BitDepth::Bpp4
+        }
+    }
+
+    /// Decides what to do about the file's contents on disk changing to `bytes_on_disk`, given the
+    /// bytes shown for it in the all-bytes array.
+    pub fn change_on_disk(&self, shown_bytes: Option<&[u8]>, bytes_on_disk: &[u8]) -> ChangeOnDisk {
+        if shown_bytes == Some(bytes_on_disk) {
+            ChangeOnDisk::AlreadyShown
+        } else if bytes_on_disk.len() != self.bytes.len() {
+            ChangeOnDisk::SizeConflict(bytes_on_disk.len())
+        } else if self.dirty {
+            ChangeOnDisk::KeepEdits
+        } else {
+            ChangeOnDisk::Reload
+        }
+    }
+
+    /// If the all-bytes array was an all-tiles array of this file's bit depth, this would be the
+    /// index of the first tile in this file.
+    pub fn first_tile_id(&self) -> u32 {
+        (self.offset_in_all_bytes / self.bit_depth.bytes_per_tile()) as u32
+    }
+
+    /// Where the file's bytes are in the all-bytes array.
+    pub fn range_in_all_bytes(&self) -> Range<usize> {
+        self.offset_in_all_bytes..self.offset_in_all_bytes + self.bytes.len()
+    }
+
+    pub fn tile_count(&self) -> u32 {
+        (self.bytes.len() / self.bit_depth.bytes_per_tile()) as u32
+    }
+
+    pub fn layout_all_tile_instances_from_file(
+        &self,
+        first_palette_color: usize,
+    ) -> Arc<Vec<tilemap::TileInstance>> {
+        let pal = self
+            .bit_depth
+            .pal_for_first_palette_color(first_palette_color);
+        let mut tile_instances = vec![];
+
+        // Each iteration of the below for-loop is a 2x2 grid of 4 tiles which here we will call a
+        // quad.
+        let bytes_per_tile = self.bit_depth.bytes_per_tile(); // it's 32 for 4bpp
+        let bytes_per_quad = bytes_per_tile * 4;
+        let number_of_quads_in_this_file = self.bytes.len() / bytes_per_quad;
+
+        let first_tile_id_of_file = self.first_tile_id();
+
+        let quads_per_row = 8;
+
+        for quad_index in 0..(number_of_quads_in_this_file) as u32 {
+            // These are in units of the visible pixels in the tile
+            let quad_left_x = quad_index % quads_per_row * 16;
+            let quad_top_y = quad_index / quads_per_row * 16;
+
+            let first_tile_id_of_quad = first_tile_id_of_file + quad_index * 4;
+
+            tile_instances.push(tilemap::TileInstance {
+                x: quad_left_x,
+                y: quad_top_y,
+                id: first_tile_id_of_quad,
+                pal,
+                scale: 1,
+                flags: 0,
+            });
+            tile_instances.push(tilemap::TileInstance {
+                x: quad_left_x + 8,
+                y: quad_top_y,
+                id: first_tile_id_of_quad + 1,
+                pal,
+                scale: 1,
+                flags: 0,
+            });
+            tile_instances.push(tilemap::TileInstance {
+                x: quad_left_x,
+                y: quad_top_y + 8,
+                id: first_tile_id_of_quad + 2,
+                pal,
+                scale: 1,
+                flags: 0,
+            });
+            tile_instances.push(tilemap::TileInstance {
+                x: quad_left_x + 8,
+                y: quad_top_y + 8,
+                id: first_tile_id_of_quad + 3,
+                pal,
+                scale: 1,
+                flags: 0,
+            });
+        }
+        for tile_instance in tile_instances.iter_mut() {
+            tile_instance.set_bit_depth(self.bit_depth);
+        }
+        Arc::new(tile_instances)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graphics_file(bytes: Vec<u8>, dirty: bool) -> GraphicsFile {
+        GraphicsFile {
+            id: 0,
+            source: GraphicsFileSource::File(PathBuf::from("/hacks/ExGFX100.bin")),
+            bytes: Arc::new(bytes),
+            offset_in_all_bytes: 0,
+            bit_depth: BitDepth::Bpp4,
+            compression: lz::Compression::None,
+            dirty,
+            size_conflict: None,
+        }
+    }
+
+    #[test]
+    fn changes_matching_whats_shown_are_already_shown() {
+        let read = vec![1; 64];
+        let edited = vec![2; 64];
+        // Such as a save made by the editor itself, which clears the unsaved edits.
+        let file = graphics_file(read, true);
+        assert_eq!(
+            file.change_on_disk(Some(&edited), &edited),
+            ChangeOnDisk::AlreadyShown
+        );
+        // Even when the size changed, since the all-bytes array already holds the new bytes.
+        let file = graphics_file(vec![1; 32], false);
+        assert_eq!(
+            file.change_on_disk(Some(&edited), &edited),
+            ChangeOnDisk::AlreadyShown
+        );
+    }
+
+    #[test]
+    fn changes_of_size_are_conflicts() {
+        let shown = vec![1; 64];
+        for dirty in [false, true] {
+            let file = graphics_file(shown.clone(), dirty);
+            assert_eq!(
+                file.change_on_disk(Some(&shown), &[3; 96]),
+                ChangeOnDisk::SizeConflict(96)
+            );
+            assert_eq!(
+                file.change_on_disk(Some(&shown), &[3; 32]),
+                ChangeOnDisk::SizeConflict(32)
+            );
+        }
+    }
+
+    #[test]
+    fn unsaved_edits_are_kept() {
+        let edited = vec![2; 64];
+        let file = graphics_file(vec![1; 64], true);
+        assert_eq!(
+            file.change_on_disk(Some(&edited), &[3; 64]),
+            ChangeOnDisk::KeepEdits
+        );
+    }
+
+    #[test]
+    fn files_without_edits_are_reloaded() {
+        let shown = vec![1; 64];
+        let file = graphics_file(shown.clone(), false);
+        assert_eq!(
+            file.change_on_disk(Some(&shown), &[3; 64]),
+            ChangeOnDisk::Reload
+        );
+    }
+}
@@ -0,0 +1,759 @@
+//! The LZ2 and LZ3 compression formats, as used by Lunar Magic (through Lunar Compress) for SMW's
+//! graphics.
+//!
+//! Both formats are a stream of chunks, each starting with a header byte `CCCLLLLL` holding a
+//! 3-bit command and a 5-bit length minus one. Command 7 means the header is two bytes long,
+//! `111CCCLL LLLLLLLL`, for lengths of up to 1024. A header byte of `0xFF` ends the stream.
+//!
+//! |Command|LZ2                              |LZ3                                           |
+//! |-------|---------------------------------|----------------------------------------------|
+//! |0      |Copy the following bytes         |Copy the following bytes                      |
+//! |1      |Repeat one byte                  |Repeat one byte                               |
+//! |2      |Alternate between two bytes      |Alternate between two bytes                   |
+//! |3      |Count up from one byte           |Fill with zeroes                              |
+//! |4      |Copy from output (absolute, BE)  |Copy from output                              |
+//! |5      |-                                |Copy from output with each byte's bits reversed|
+//! |6      |-                                |Copy from output going backwards              |
+//!
+//! LZ2 addresses earlier output with a 2-byte big endian offset. LZ3 does the same if the first
+//! byte's top bit is clear, and otherwise uses the remaining 7 bits as a distance back from the
+//! current position, minus one.
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz2,
+    Lz3,
+}
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::None => write!(f, "Uncompressed"),
+            Self::Lz2 => write!(f, "LZ2"),
+            Self::Lz3 => write!(f, "LZ3"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecompressError {
+    /// The input ran out before the end-of-stream marker.
+    UnexpectedEnd,
+    /// A header used a command that doesn't exist in the format.
+    InvalidCommand(u8),
+    /// A command tried to copy from a position that hasn't been output yet.
+    AddressOutOfRange(usize),
+}
+impl std::fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedEnd => write!(f, "compressed data ended without an end marker"),
+            Self::InvalidCommand(command) => write!(f, "invalid command {command}"),
+            Self::AddressOutOfRange(address) => {
+                write!(
+                    f,
+                    "copy from address {address:#X} which is not yet decompressed"
+                )
+            }
+        }
+    }
+}
+
+const END_OF_STREAM: u8 = 0xFF;
+const MAX_CHUNK_LENGTH: usize = 1024;
+const MAX_SHORT_CHUNK_LENGTH: usize = 32;
+
+const COMMAND_DIRECT_COPY: u8 = 0;
+const COMMAND_BYTE_FILL: u8 = 1;
+const COMMAND_WORD_FILL: u8 = 2;
+/// Increasing fill in LZ2, zero fill in LZ3.
+const COMMAND_INCREASING_OR_ZERO_FILL: u8 = 3;
+const COMMAND_REPEAT: u8 = 4;
+const COMMAND_BIT_REVERSED_REPEAT: u8 = 5;
+const COMMAND_BACKWARDS_REPEAT: u8 = 6;
+const COMMAND_LONG_HEADER: u8 = 7;
+
+/// Looks at whether the bytes are a complete LZ2 or LZ3 stream, and decompresses them if so.
+/// Anything else is assumed to be uncompressed and returned as is.
+///
+/// A stream only counts as compressed if it decodes without errors, its end marker is the last byte
+/// and it decompresses to a whole number of 2bpp tiles, which raw graphics are very unlikely to do.
+pub fn detect_and_decompress(bytes: Vec<u8>) -> (Compression, Vec<u8>) {
+    COMPRESSED_FORMATS
+        .into_iter()
+        .find_map(
+            |compression| match decompress_graphics(compression, &bytes) {
+                Some((decompressed, compressed_len)) if compressed_len == bytes.len() => {
+                    Some((compression, decompressed))
+                }
+                _ => None,
+            },
+        )
+        .unwrap_or((Compression::None, bytes))
+}
+
+/// Like [`detect_and_decompress`], but for a stream that's followed by other data, such as
+/// graphics in a ROM. Returns which format the stream is in, the decompressed bytes, and how many
+/// bytes the stream took up.
+pub fn detect_and_decompress_stream(input: &[u8]) -> Option<(Compression, Vec<u8>, usize)> {
+    COMPRESSED_FORMATS.into_iter().find_map(|compression| {
+        decompress_graphics(compression, input)
+            .map(|(decompressed, compressed_len)| (compression, decompressed, compressed_len))
+    })
+}
+
+/// LZ2 is tried first, since it's what SMW itself uses.
+const COMPRESSED_FORMATS: [Compression; 2] = [Compression::Lz2, Compression::Lz3];
+
+fn decompress_graphics(compression: Compression, input: &[u8]) -> Option<(Vec<u8>, usize)> {
+    decompress(compression, input)
+        .ok()
+        .filter(|(decompressed, _)| !decompressed.is_empty() && decompressed.len() % 16 == 0)
+}
+
+/// Decompresses a stream from the start of `input`, returning the decompressed bytes and how many
+/// bytes of input the stream took up, including the end marker. Anything after the end marker is
+/// ignored, so this can be pointed at compressed data in the middle of a ROM.
+pub fn decompress(
+    compression: Compression,
+    input: &[u8],
+) -> Result<(Vec<u8>, usize), DecompressError> {
+    if compression == Compression::None {
+        return Ok((input.to_vec(), input.len()));
+    }
+
+    let mut output = Vec::new();
+    let mut position = 0;
+    let mut next_byte = || -> Result<u8, DecompressError> {
+        let byte = *input.get(position).ok_or(DecompressError::UnexpectedEnd)?;
+        position += 1;
+        Ok(byte)
+    };
+
+    loop {
+        let header = next_byte()?;
+        if header == END_OF_STREAM {
+            break;
+        }
+        let (command, length) = if header >> 5 == COMMAND_LONG_HEADER {
+            let length_low = next_byte()?;
+            (
+                (header >> 2) & 0b111,
+                ((header as usize & 0b11) << 8 | length_low as usize) + 1,
+            )
+        } else {
+            (header >> 5, (header as usize & 0b11111) + 1)
+        };
+
+        match (compression, command) {
+            (_, COMMAND_DIRECT_COPY) => {
+                for _ in 0..length {
+                    output.push(next_byte()?);
+                }
+            }
+            (_, COMMAND_BYTE_FILL) => {
+                let byte = next_byte()?;
+                output.extend(std::iter::repeat_n(byte, length));
+            }
+            (_, COMMAND_WORD_FILL) => {
+                let bytes = [next_byte()?, next_byte()?];
+                output.extend((0..length).map(|i| bytes[i % 2]));
+            }
+            (Compression::Lz2, COMMAND_INCREASING_OR_ZERO_FILL) => {
+                let byte = next_byte()?;
+                output.extend((0..length).map(|i| byte.wrapping_add(i as u8)));
+            }
+            (Compression::Lz3, COMMAND_INCREASING_OR_ZERO_FILL) => {
+                output.extend(std::iter::repeat_n(0, length));
+            }
+            (Compression::Lz2, COMMAND_REPEAT) => {
+                let address = (next_byte()? as usize) << 8 | next_byte()? as usize;
+                repeat_from_output(&mut output, address, length, |output, i| {
+                    output[address + i]
+                })?;
+            }
+            (
+                Compression::Lz3,
+                COMMAND_REPEAT | COMMAND_BIT_REVERSED_REPEAT | COMMAND_BACKWARDS_REPEAT,
+            ) => {
+                let first = next_byte()?;
+                let address = if first & 0x80 != 0 {
+                    output
+                        .len()
+                        .checked_sub((first & 0x7F) as usize + 1)
+                        .ok_or(DecompressError::AddressOutOfRange(0))?
+                } else {
+                    (first as usize) << 8 | next_byte()? as usize
+                };
+                match command {
+                    COMMAND_REPEAT => {
+                        repeat_from_output(&mut output, address, length, |output, i| {
+                            output[address + i]
+                        })?
+                    }
+                    COMMAND_BIT_REVERSED_REPEAT => {
+                        repeat_from_output(&mut output, address, length, |output, i| {
+                            output[address + i].reverse_bits()
+                        })?
+                    }
+                    _ => {
+                        if address + 1 < length {
+                            return Err(DecompressError::AddressOutOfRange(address));
+                        }
+                        repeat_from_output(&mut output, address, length, |output, i| {
+                            output[address - i]
+                        })?
+                    }
+                }
+            }
+            _ => return Err(DecompressError::InvalidCommand(command)),
+        }
+    }
+
+    Ok((output, position))
+}
+
+/// Appends `length` bytes to the output, each picked out of the output so far by `byte_at`. Copies
+/// may overlap the bytes they're producing, which is how runs of a repeating pattern are encoded.
+fn repeat_from_output(
+    output: &mut Vec<u8>,
+    address: usize,
+    length: usize,
+    byte_at: impl Fn(&[u8], usize) -> u8,
+) -> Result<(), DecompressError> {
+    if address >= output.len() {
+        return Err(DecompressError::AddressOutOfRange(address));
+    }
+    for i in 0..length {
+        let byte = byte_at(output, i);
+        output.push(byte);
+    }
+    Ok(())
+}
+
+/// Compresses the bytes so that [`decompress`] gives them back exactly.
+///
+/// At each position this picks whichever command saves the most bytes, falling back to direct
+/// copies. In LZ3 that includes bit-reversed and backwards repeats, which find tiles flipped
+/// horizontally and runs of bytes mirrored around a point.
+pub fn compress(compression: Compression, input: &[u8]) -> Vec<u8> {
+    if compression == Compression::None {
+        return input.to_vec();
+    }
+
+    let mut output = Vec::new();
+    let mut literal_start = 0;
+    let mut repeat_finder = RepeatFinder::default();
+    let mut position = 0;
+
+    while position < input.len() {
+        match best_chunk(compression, input, position, &repeat_finder) {
+            Some(chunk) => {
+                write_direct_copy(&mut output, &input[literal_start..position]);
+                let length = chunk.length();
+                chunk.write(&mut output);
+                for _ in 0..length {
+                    repeat_finder.insert(input, position);
+                    position += 1;
+                }
+                literal_start = position;
+            }
+            None => {
+                repeat_finder.insert(input, position);
+                position += 1;
+            }
+        }
+    }
+    write_direct_copy(&mut output, &input[literal_start..position]);
+    output.push(END_OF_STREAM);
+    output
+}
+
+/// Any command other than a direct copy.
+enum Chunk {
+    ByteFill(u8, usize),
+    WordFill([u8; 2], usize),
+    IncreasingFill(u8, usize),
+    ZeroFill(usize),
+    Repeat {
+        compression: Compression,
+        kind: RepeatKind,
+        address: usize,
+        distance: usize,
+        length: usize,
+    },
+}
+
+/// The ways a repeat can copy from earlier output. Only LZ3 has the last two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RepeatKind {
+    Forwards,
+    BitReversed,
+    Backwards,
+}
+impl RepeatKind {
+    const ALL: [RepeatKind; 3] = [Self::Forwards, Self::BitReversed, Self::Backwards];
+
+    fn command(self) -> u8 {
+        match self {
+            Self::Forwards => COMMAND_REPEAT,
+            Self::BitReversed => COMMAND_BIT_REVERSED_REPEAT,
+            Self::Backwards => COMMAND_BACKWARDS_REPEAT,
+        }
+    }
+}
+impl Chunk {
+    fn length(&self) -> usize {
+        match *self {
+            Self::ByteFill(_, length)
+            | Self::WordFill(_, length)
+            | Self::IncreasingFill(_, length)
+            | Self::ZeroFill(length)
+            | Self::Repeat { length, .. } => length,
+        }
+    }
+
+    fn argument_len(&self) -> usize {
+        match *self {
+            Self::ByteFill(..) | Self::IncreasingFill(..) => 1,
+            Self::WordFill(..) => 2,
+            Self::ZeroFill(..) => 0,
+            Self::Repeat {
+                compression: Compression::Lz3,
+                distance,
+                ..
+            } if distance <= 0x80 => 1,
+            Self::Repeat { .. } => 2,
+        }
+    }
+
+    /// How many fewer bytes this takes than copying its bytes directly.
+    fn savings(&self) -> isize {
+        self.length() as isize - (header_len(self.length()) + self.argument_len()) as isize
+    }
+
+    fn write(&self, output: &mut Vec<u8>) {
+        match *self {
+            Self::ByteFill(byte, length) => {
+                write_header(output, COMMAND_BYTE_FILL, length);
+                output.push(byte);
+            }
+            Self::WordFill(bytes, length) => {
+                write_header(output, COMMAND_WORD_FILL, length);
+                output.extend(bytes);
+            }
+            Self::IncreasingFill(byte, length) => {
+                write_header(output, COMMAND_INCREASING_OR_ZERO_FILL, length);
+                output.push(byte);
+            }
+            Self::ZeroFill(length) => {
+                write_header(output, COMMAND_INCREASING_OR_ZERO_FILL, length);
+            }
+            Self::Repeat {
+                kind,
+                address,
+                distance,
+                length,
+                ..
+            } => {
+                write_header(output, kind.command(), length);
+                if self.argument_len() == 1 {
+                    output.push(0x80 | (distance - 1) as u8);
+                } else {
+                    output.extend((address as u16).to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+fn best_chunk(
+    compression: Compression,
+    input: &[u8],
+    position: usize,
+    repeat_finder: &RepeatFinder,
+) -> Option<Chunk> {
+    let remaining = &input[position..];
+    let max_length = remaining.len().min(MAX_CHUNK_LENGTH);
+    let run_length =
+        |matches: &dyn Fn(usize) -> bool| (0..max_length).take_while(|&i| matches(i)).count();
+
+    let first = remaining[0];
+    let mut candidates = vec![];
+    let byte_fill_length = run_length(&|i| remaining[i] == first);
+    if compression == Compression::Lz3 && first == 0 {
+        candidates.push(Chunk::ZeroFill(byte_fill_length));
+    } else {
+        candidates.push(Chunk::ByteFill(first, byte_fill_length));
+    }
+    if let Some(&second) = remaining.get(1) {
+        let bytes = [first, second];
+        candidates.push(Chunk::WordFill(
+            bytes,
+            run_length(&|i| remaining[i] == bytes[i % 2]),
+        ));
+    }
+    if compression == Compression::Lz2 {
+        candidates.push(Chunk::IncreasingFill(
+            first,
+            run_length(&|i| remaining[i] == first.wrapping_add(i as u8)),
+        ));
+    }
+    let repeat_kinds = match compression {
+        Compression::Lz3 => &RepeatKind::ALL[..],
+        _ => &RepeatKind::ALL[..1],
+    };
+    for &kind in repeat_kinds {
+        if let Some((address, length)) =
+            repeat_finder.longest_match(compression, kind, input, position, max_length)
+        {
+            candidates.push(Chunk::Repeat {
+                compression,
+                kind,
+                address,
+                distance: position - address,
+                length,
+            });
+        }
+    }
+
+    candidates
+        .into_iter()
+        .filter(|chunk| chunk.savings() > 0)
+        .max_by_key(|chunk| (chunk.savings(), chunk.length()))
+}
+
+/// Remembers where each 3-byte sequence has appeared, to find repeats without scanning everything
+/// that came before.
+#[derive(Default)]
+struct RepeatFinder {
+    positions_by_prefix: HashMap<[u8; 3], Vec<usize>>,
+}
+impl RepeatFinder {
+    /// How many of the most recent positions of a prefix get checked for a match.
+    const MAX_CANDIDATES: usize = 256;
+
+    fn insert(&mut self, input: &[u8], position: usize) {
+        if let Some(prefix) = Self::prefix_at(input, position) {
+            self.positions_by_prefix
+                .entry(prefix)
+                .or_default()
+                .push(position);
+        }
+    }
+
+    /// The longest repeat of the given kind that gives the bytes at `position`, as the address it
+    /// copies from and its length. Ties go to the nearest address.
+    fn longest_match(
+        &self,
+        compression: Compression,
+        kind: RepeatKind,
+        input: &[u8],
+        position: usize,
+        max_length: usize,
+    ) -> Option<(usize, usize)> {
+        let [a, b, c] = Self::prefix_at(input, position)?;
+        // The earlier 3 bytes that the repeat's first 3 bytes come from, and where the copy starts
+        // relative to them. Backwards repeats start from the last of them.
+        let (prefix, address_in_prefix) = match kind {
+            RepeatKind::Forwards => ([a, b, c], 0),
+            RepeatKind::BitReversed => ([a, b, c].map(u8::reverse_bits), 0),
+            RepeatKind::Backwards => ([c, b, a], 2),
+        };
+        // LZ3's absolute addresses only have 15 bits, but nearby ones can use a relative distance.
+        let max_address = match compression {
+            Compression::Lz3 => 0x7FFF,
+            _ => 0xFFFF,
+        };
+        self.positions_by_prefix
+            .get(&prefix)?
+            .iter()
+            .rev()
+            .take(Self::MAX_CANDIDATES)
+            .map(|&prefix_position| prefix_position + address_in_prefix)
+            // Backwards repeats can only start from bytes that are already output.
+            .filter(|&address| address < position)
+            .filter(|&address| {
+                address <= max_address
+                    || (compression == Compression::Lz3 && position - address <= 0x80)
+            })
+            .map(|address| {
+                let max_length = match kind {
+                    RepeatKind::Backwards => max_length.min(address + 1),
+                    _ => max_length,
+                };
+                let length = (0..max_length)
+                    .take_while(|&i| {
+                        input[position + i]
+                            == match kind {
+                                RepeatKind::Forwards => input[address + i],
+                                RepeatKind::BitReversed => input[address + i].reverse_bits(),
+                                RepeatKind::Backwards => input[address - i],
+                            }
+                    })
+                    .count();
+                (address, length)
+            })
+            .max_by_key(|&(address, length)| (length, address))
+    }
+
+    fn prefix_at(input: &[u8], position: usize) -> Option<[u8; 3]> {
+        input.get(position..position + 3)?.try_into().ok()
+    }
+}
+
+fn header_len(length: usize) -> usize {
+    if length > MAX_SHORT_CHUNK_LENGTH {
+        2
+    } else {
+        1
+    }
+}
+
+fn write_header(output: &mut Vec<u8>, command: u8, length: usize) {
+    let length_minus_one = length - 1;
+    if length > MAX_SHORT_CHUNK_LENGTH {
+        output.push(COMMAND_LONG_HEADER << 5 | command << 2 | (length_minus_one >> 8) as u8);
+        output.push(length_minus_one as u8);
+    } else {
+        output.push(command << 5 | length_minus_one as u8);
+    }
+}
+
+fn write_direct_copy(output: &mut Vec<u8>, bytes: &[u8]) {
+    for chunk in bytes.chunks(MAX_CHUNK_LENGTH) {
+        write_header(output, COMMAND_DIRECT_COPY, chunk.len());
+        output.extend(chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [Compression; 2] = [Compression::Lz2, Compression::Lz3];
+
+    /// An LZ2 stream using every LZ2 command, assembled by hand.
+    const LZ2_STREAM: [u8; 20] = [
+        0x03, b'A', b'B', b'C', b'D', // Copy 4 bytes
+        0x24, 0x11, // Repeat 0x11 5 times
+        0x43, 0xAA, 0x55, // Alternate between 0xAA and 0x55 for 4 bytes
+        0x62, 0x10, // Count up from 0x10 for 3 bytes
+        0x83, 0x00, 0x00, // Copy 4 bytes from address 0
+        0xFF, // End
+        0x12, 0x34, 0x56, 0x78, // Whatever comes after the stream
+    ];
+    const LZ2_DECOMPRESSED: [u8; 20] = [
+        b'A', b'B', b'C', b'D', 0x11, 0x11, 0x11, 0x11, 0x11, 0xAA, 0x55, 0xAA, 0x55, 0x10, 0x11,
+        0x12, b'A', b'B', b'C', b'D',
+    ];
+
+    /// An LZ3 stream using every LZ3 command, and both ways of addressing earlier output, assembled
+    /// by hand.
+    const LZ3_STREAM: [u8; 14] = [
+        0x03, 0x01, 0x02, 0x04, 0x08, // Copy 4 bytes
+        0x62, // 3 zeroes
+        0xA1, 0x00, 0x00, // Copy 2 bytes from address 0 with their bits reversed
+        0xC3, 0x85, // Copy 4 bytes going backwards from 6 bytes back
+        0x81, 0x8C, // Copy 2 bytes from 13 bytes back
+        0xFF, // End
+    ];
+    const LZ3_DECOMPRESSED: [u8; 15] = [
+        0x01, 0x02, 0x04, 0x08, 0x00, 0x00, 0x00, 0x80, 0x40, 0x08, 0x04, 0x02, 0x01, 0x01, 0x02,
+    ];
+
+    /// Bytes from a xorshift generator, so that tests don't depend on a random number crate.
+    fn random_bytes(len: usize, mut state: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn assert_round_trips(input: &[u8]) {
+        for compression in FORMATS {
+            let compressed = compress(compression, input);
+            assert_eq!(
+                decompress(compression, &compressed),
+                Ok((input.to_vec(), compressed.len())),
+                "{compression} round trip of {} bytes",
+                input.len()
+            );
+        }
+    }
+
+    #[test]
+    fn decompresses_known_lz2_stream() {
+        assert_eq!(
+            decompress(Compression::Lz2, &LZ2_STREAM),
+            Ok((LZ2_DECOMPRESSED.to_vec(), 16))
+        );
+    }
+
+    #[test]
+    fn decompresses_known_lz3_stream() {
+        assert_eq!(
+            decompress(Compression::Lz3, &LZ3_STREAM),
+            Ok((LZ3_DECOMPRESSED.to_vec(), LZ3_STREAM.len()))
+        );
+    }
+
+    #[test]
+    fn round_trips_empty_input() {
+        assert_round_trips(&[]);
+        assert_eq!(compress(Compression::Lz2, &[]), [END_OF_STREAM]);
+    }
+
+    #[test]
+    fn round_trips_all_zero_input() {
+        assert_round_trips(&[0; 4096]);
+    }
+
+    #[test]
+    fn round_trips_incrementing_input() {
+        assert_round_trips(&(0..5000).map(|i| i as u8).collect::<Vec<_>>());
+        // Far enough apart that LZ3 has to use absolute addresses, and past where it can.
+        assert_round_trips(&(0..40000).map(|i| (i % 1000) as u8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn round_trips_random_input() {
+        assert_round_trips(&random_bytes(8192, 0x1234_5678));
+        // Random with repeats of earlier stretches.
+        let mut input = random_bytes(2048, 0x9ABC_DEF0);
+        input.extend_from_within(100..900);
+        input.extend(random_bytes(100, 1));
+        input.extend_from_within(0..2000);
+        assert_round_trips(&input);
+    }
+
+    #[test]
+    fn lz3_repeats_flipped_and_mirrored_bytes() {
+        let tile = random_bytes(32, 0x0BAD_CAFE);
+        let mut flipped = tile.clone();
+        flipped.extend(tile.iter().map(|byte| byte.reverse_bits()));
+        let mut expected = vec![0x1F];
+        expected.extend(&tile);
+        // A bit-reversed repeat of 32 bytes from 32 bytes back.
+        expected.extend([0xBF, 0x9F, END_OF_STREAM]);
+        assert_eq!(compress(Compression::Lz3, &flipped), expected);
+
+        let mut mirrored = tile.clone();
+        mirrored.extend(tile.iter().rev());
+        let mut expected = vec![0x1F];
+        expected.extend(&tile);
+        // A backwards repeat of 32 bytes from the byte just before it.
+        expected.extend([0xDF, 0x80, END_OF_STREAM]);
+        assert_eq!(compress(Compression::Lz3, &mirrored), expected);
+
+        assert_round_trips(&flipped);
+        assert_round_trips(&mirrored);
+    }
+
+    #[test]
+    fn long_headers() {
+        // Command 7 with byte fill inside: 111 001 00, then the length minus one.
+        let stream = [0xE4, 0x63, 0x7E, 0xFF];
+        for compression in FORMATS {
+            assert_eq!(
+                decompress(compression, &stream),
+                Ok((vec![0x7E; 100], stream.len()))
+            );
+            assert_eq!(compress(compression, &[0x7E; 100]), stream);
+        }
+        // The longest chunk there is.
+        assert_eq!(
+            decompress(Compression::Lz2, &[0xE7, 0xFF, 0x7E, 0xFF]),
+            Ok((vec![0x7E; MAX_CHUNK_LENGTH], 4))
+        );
+        assert_round_trips(&[0x7E; MAX_CHUNK_LENGTH * 3 + 5]);
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        for (compression, stream) in [
+            (Compression::Lz2, &LZ2_STREAM[..16]),
+            (Compression::Lz3, &LZ3_STREAM[..]),
+        ] {
+            for len in 0..stream.len() - 1 {
+                assert_eq!(
+                    decompress(compression, &stream[..len]),
+                    Err(DecompressError::UnexpectedEnd),
+                    "{compression} stream cut to {len} bytes"
+                );
+            }
+        }
+        // Cut off between the two bytes of a long header.
+        assert_eq!(
+            decompress(Compression::Lz2, &[0xE4]),
+            Err(DecompressError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn invalid_streams_are_errors() {
+        assert_eq!(
+            decompress(Compression::Lz2, &[0xA0, 0x00, 0x00, 0xFF]),
+            Err(DecompressError::InvalidCommand(COMMAND_BIT_REVERSED_REPEAT))
+        );
+        assert_eq!(
+            decompress(Compression::Lz2, &[0x80, 0x00, 0x00, 0xFF]),
+            Err(DecompressError::AddressOutOfRange(0))
+        );
+    }
+
+    /// Checks every compressed file in `tests/fixtures/lz` against the decompressed `.bin` file
+    /// with the same name. Compressing again isn't expected to give the same stream back, see the
+    /// README there.
+    #[test]
+    fn matches_fixtures() {
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/lz");
+        let mut fixture_count = 0;
+        for entry in std::fs::read_dir(&fixtures).unwrap() {
+            let path = entry.unwrap().path();
+            let compression = match path.extension().and_then(|extension| extension.to_str()) {
+                Some("lz2") => Compression::Lz2,
+                Some("lz3") => Compression::Lz3,
+                _ => continue,
+            };
+            let compressed = std::fs::read(&path).unwrap();
+            let decompressed = std::fs::read(path.with_extension("bin")).unwrap();
+            assert_eq!(
+                decompress(compression, &compressed),
+                Ok((decompressed.clone(), compressed.len())),
+                "{path:?}"
+            );
+            let recompressed = compress(compression, &decompressed);
+            assert_eq!(
+                decompress(compression, &recompressed),
+                Ok((decompressed, recompressed.len())),
+                "{path:?} compressed again"
+            );
+            fixture_count += 1;
+        }
+        assert!(fixture_count > 0, "No fixtures in {fixtures:?}");
+    }
+
+    #[test]
+    fn detects_compression() {
+        let graphics = random_bytes(0x400, 42);
+        for compression in FORMATS {
+            let compressed = compress(compression, &graphics);
+            let (detected, decompressed) = detect_and_decompress(compressed);
+            assert_eq!(decompressed, graphics);
+            // A stream can be valid in both formats, but then both decompress it the same.
+            assert_ne!(detected, Compression::None);
+        }
+        assert_eq!(
+            detect_and_decompress(graphics.clone()),
+            (Compression::None, graphics)
+        );
+    }
+}
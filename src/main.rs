@@ -1,16 +1,42 @@
+mod bgr555;
+mod block_library;
+mod color_editor;
+mod color_reduction;
+mod file_watcher;
+mod gradient;
+mod graphics_file;
+mod lz;
 mod palette;
+mod palette_animation;
+mod palette_file;
+mod pixel_editor;
+mod planar;
+mod png_sheet;
+mod project;
+mod rom;
 mod tilemap;
 
 use std::{
-    path::PathBuf,
+    collections::HashMap,
+    path::{Path, PathBuf},
     sync::{Arc, RwLock},
 };
 
-use iced::{application, window, Alignment, Element, Length, Point, Settings, Size, Task, Theme};
-use tilemap::{TileCoords, TileInstance};
+use block_library::BlockLibraryTile;
+use graphics_file::{
+    load_file, open_rom, pick_graphics_file_to_save, pick_graphics_files, save_file, ChangeOnDisk,
+    GraphicsFile, GraphicsFileSource, GraphicsSlot,
+};
+
+use iced::{
+    application, keyboard, window, Alignment, Color, Element, Length, Point, Settings, Size,
+    Subscription, Task, Theme,
+};
+use tilemap::{BitDepth, GraphicsBytes, TileCoords, TileInstance};
 
 fn main() -> iced::Result {
     application("Piped Mockup", App::update, App::view)
+        .subscription(App::subscription)
         .theme(|_| Theme::Dark)
         .settings(Settings {
             antialiasing: true,
@@ -25,11 +51,68 @@ fn main() -> iced::Result {
 }
 
 struct App {
+    /// Where the project was last opened from or saved to.
+    project_path: Option<PathBuf>,
+    palette_path: PathBuf,
+    /// Why the palette couldn't be loaded, the last time it was tried.
+    palette_error: Option<palette_file::LoadError>,
+    /// Set when the palette file changed on disk while the palette had unsaved edits, which were
+    /// kept.
+    palette_conflict: bool,
     displayed_graphics_file_component: Option<tilemap::Component>,
     palette_selector: palette::Component,
+    color_editor: color_editor::Component,
+    /// The index in the palette of the color under the cursor.
+    palette_color_hovered: Option<usize>,
+    displayed_graphics_file: Option<usize>,
     graphics_files: Vec<GraphicsFile>,
-    all_graphics_bytes: Arc<RwLock<Vec<u8>>>,
+    /// The id the next graphics file added gets.
+    next_graphics_file_id: u64,
+    /// Which file, by index into `graphics_files`, each slot is bound to.
+    graphics_slots: HashMap<GraphicsSlot, usize>,
+    all_graphics_bytes: Arc<RwLock<GraphicsBytes>>,
     displayed_block_library: Option<tilemap::Component>,
+    block_library_tiles: Vec<BlockLibraryTile>,
+    /// Whether tiles are painted into the block library mirrored left to right.
+    brush_flip_x: bool,
+    /// Whether tiles are painted into the block library mirrored top to bottom.
+    brush_flip_y: bool,
+    /// The layer tiles are painted into the block library on.
+    brush_layer: tilemap::Layer,
+    brush_priority: u8,
+    /// How many times their normal size tiles are painted into the block library.
+    brush_scale: u8,
+    /// Whether the block library tints high priority tiles.
+    highlight_priority: bool,
+    /// The palette shared by every component that shows it.
+    palette: Arc<RwLock<palette::Palette>>,
+    pixel_editor: pixel_editor::Component,
+    /// Whether the pixel editor shows the whole quad around the brush tile, rather than just it.
+    pixel_editor_edits_quad: bool,
+    pixel_editor_target: Option<PixelEditorTarget>,
+    png_sheet_layout: png_sheet::SheetLayout,
+    png_color_zero: png_sheet::ColorZero,
+    /// Which pixels of the last imported PNG had colors that aren't in the palette, if any did.
+    import_warning: Option<String>,
+    /// How many palette lines, starting at the selected one, converted images may use.
+    conversion_palette_lines: usize,
+    /// Why the last converted image couldn't be added, or painted into the block library.
+    conversion_error: Option<String>,
+    palette_export_format: palette_file::ExportFormat,
+    palette_export_lines: palette_file::ExportedLines,
+    palette_animations: palette_animation::Component,
+    /// The master brightness used for SNES output, kept while colors are shown as they are.
+    snes_brightness: u8,
+    gradient: gradient::Component,
+}
+
+/// The tiles shown in the pixel editor.
+struct PixelEditorTarget {
+    bit_depth: BitDepth,
+    /// The index in the palette of the tiles' color 0.
+    first_color: usize,
+    /// Each tile's id, along with where it is in the pixel editor, in tiles.
+    tiles: Vec<(u32, TileCoords)>,
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -38,119 +121,313 @@ struct App {
 enum Message {
     FromDisplayedGraphicsFile(tilemap::Envelope),
     FromDisplayedBlockLibrary(tilemap::Envelope),
+    ToggleBrushFlipX,
+    ToggleBrushFlipY,
+    SetBrushLayer(tilemap::Layer),
+    SetBrushPriority(u8),
+    SetBrushScale(u8),
+    SetHighlightPriority(bool),
     FromPaletteSelector(palette::Envelope),
-    GraphicsFileLoaded(Option<(PathBuf, Arc<Vec<u8>>)>),
+    FromColorEditor(color_editor::Envelope),
+    EditPaletteColor(usize),
+    SetPaletteContext(palette::PaletteContext),
+    SetSnesOutput(bool),
+    SetSnesBrightness(u8),
+    FromGradient(gradient::Envelope),
+    FromPaletteAnimations(palette_animation::Envelope),
+    FromPixelEditor(pixel_editor::Envelope),
+    SetPixelEditorEditsQuad(bool),
+    GraphicsFileLoaded(Option<(PathBuf, Arc<Vec<u8>>, lz::Compression)>),
     DisplayGraphicsFile(usize),
-    LoadMoreGraphicsFiles,
+    SetGraphicsFileBitDepth(usize, BitDepth),
+    SetGraphicsFileSlot(usize, GraphicsSlot),
+    OpenGraphicsFiles,
+    GraphicsFilesPicked(Vec<PathBuf>),
+    SaveGraphicsFiles,
+    /// Carries the saved file's id, and where it was saved to along with the bytes saved.
+    GraphicsFileSaved(u64, Option<(PathBuf, Arc<Vec<u8>>)>),
+    OpenRom,
+    RomLoaded(Option<(PathBuf, Arc<Vec<rom::RomGraphicsFile>>)>),
+    WatchedFileChanged(PathBuf),
+    GraphicsFileChangedOnDisk(Option<(PathBuf, Arc<Vec<u8>>, lz::Compression)>),
+    RomChangedOnDisk(Option<(PathBuf, Arc<Vec<rom::RomGraphicsFile>>)>),
+    OpenPalette,
+    PaletteFilePicked(Option<PathBuf>),
+    /// Rereads the palette file, dropping any unsaved edits.
+    ReloadPalette,
+    SetPaletteExportFormat(palette_file::ExportFormat),
+    SetPaletteExportLines(palette_file::ExportedLines),
+    ExportPalette,
+    PaletteExported(Option<PathBuf>),
+    SetPngSheetLayout(png_sheet::SheetLayout),
+    SetPngColorZero(png_sheet::ColorZero),
+    ExportDisplayedGraphicsFilePng,
+    ExportAllGraphicsPng,
+    PngExported(Option<PathBuf>),
+    ImportPngAsNewFile,
+    ImportPngIntoDisplayedFile,
+    /// Carries the index of the file to import into, or None to import as a new file.
+    PngToImportLoaded(Option<(PathBuf, Arc<image::RgbaImage>)>, Option<usize>),
+    SetConversionPaletteLines(usize),
+    ConvertImage,
+    ImageConverted(Option<(PathBuf, Arc<color_reduction::Conversion>)>),
+    NewProject,
+    OpenProject,
+    ProjectOpened(Option<Arc<project::OpenedProject>>),
+    SaveProject,
+    SaveProjectAs,
+    ProjectSaved(Option<PathBuf>),
     MouseMovedOverPalette(Point),
     MousePressedOverPalette,
 }
 impl App {
     fn new() -> (Self, Task<Message>) {
-        (
-            App {
-                displayed_graphics_file_component: None,
-                palette_selector: palette::Component::new(),
-                graphics_files: vec![],
-                all_graphics_bytes: Arc::new(RwLock::new(vec![])),
-                displayed_block_library: None,
-            },
-            Task::batch([
-                Task::perform(
-                    load_file(PathBuf::from(format!(
-                        "{}/assets/global.bin",
-                        env!("CARGO_MANIFEST_DIR")
-                    ))),
-                    Message::GraphicsFileLoaded,
-                ),
-                Task::perform(
-                    load_file(PathBuf::from(format!(
-                        "{}/assets/grass.bin",
-                        env!("CARGO_MANIFEST_DIR")
-                    ))),
-                    Message::GraphicsFileLoaded,
-                ),
-                Task::perform(
-                    load_file(PathBuf::from(format!(
-                        "{}/assets/onoff.bin",
-                        env!("CARGO_MANIFEST_DIR")
-                    ))),
-                    Message::GraphicsFileLoaded,
-                ),
-                Task::perform(
-                    load_file(PathBuf::from(format!(
-                        "{}/assets/pswitch.bin",
-                        env!("CARGO_MANIFEST_DIR")
-                    ))),
-                    Message::GraphicsFileLoaded,
-                ),
-            ]),
-        )
+        let palette_path = PathBuf::from(palette_file::DEFAULT_PALETTE_PATH);
+        let palette: Arc<RwLock<palette::Palette>> = Default::default();
+        let mut app = App {
+            project_path: None,
+            palette_path: std::path::absolute(&palette_path).unwrap_or(palette_path),
+            palette_error: None,
+            palette_conflict: false,
+            palette: palette.clone(),
+            displayed_graphics_file_component: None,
+            palette_selector: palette::Component::new(palette.clone()),
+            color_editor: color_editor::Component::new(),
+            palette_color_hovered: None,
+            displayed_graphics_file: None,
+            graphics_files: vec![],
+            next_graphics_file_id: 0,
+            graphics_slots: HashMap::new(),
+            all_graphics_bytes: Default::default(),
+            displayed_block_library: None,
+            block_library_tiles: vec![],
+            brush_flip_x: false,
+            brush_flip_y: false,
+            brush_layer: Default::default(),
+            brush_priority: 0,
+            brush_scale: 1,
+            highlight_priority: false,
+            pixel_editor: pixel_editor::Component::new(),
+            pixel_editor_edits_quad: false,
+            pixel_editor_target: None,
+            png_sheet_layout: Default::default(),
+            png_color_zero: Default::default(),
+            conversion_palette_lines: 1,
+            import_warning: None,
+            conversion_error: None,
+            palette_export_format: Default::default(),
+            palette_export_lines: Default::default(),
+            palette_animations: palette_animation::Component::new(palette.clone()),
+            snes_brightness: 15,
+            gradient: gradient::Component::new(palette),
+        };
+        app.reload_palette(false);
+        (app, Task::none())
     }
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
-            Message::GraphicsFileLoaded(Some((path, bytes))) => {
-                println!("loaded {path:?}, {:?} bytes", bytes.len());
-                let file = GraphicsFile {
-                    path,
-                    bytes: bytes.clone(),
-                    offset_in_all_bytes: self.all_graphics_bytes.read().unwrap().len(),
-                };
-
-                if self.displayed_graphics_file_component.is_none() {
-                    self.displayed_graphics_file_component = Some(tilemap::Component::new(
-                        self.all_graphics_bytes.clone(),
-                        file.layout_all_tile_instances_from_file(
-                            self.palette_selector.selected_line,
-                        ),
+            Message::GraphicsFileLoaded(Some((path, bytes, compression))) => {
+                println!("loaded {path:?}, {:?} bytes, {compression}", bytes.len());
+                let bit_depth = GraphicsFile::guess_bit_depth(bytes.len());
+                self.add_graphics_file(
+                    GraphicsFileSource::File(path),
+                    bytes,
+                    compression,
+                    bit_depth,
+                );
+                self.bind_to_free_slot(self.graphics_files.len() - 1);
+                self.refresh_block_library();
+                Task::none()
+            }
+            Message::OpenRom => Task::perform(open_rom(), Message::RomLoaded),
+            Message::RomLoaded(Some((path, rom_graphics_files))) => {
+                for rom_graphics_file in rom_graphics_files.iter() {
+                    self.add_graphics_file(
+                        GraphicsFileSource::Rom(path.clone(), rom_graphics_file.gfx_number),
+                        Arc::new(rom_graphics_file.bytes.clone()),
+                        rom_graphics_file.compression,
+                        rom_graphics_file.bit_depth,
+                    );
+                    self.bind_to_free_slot(self.graphics_files.len() - 1);
+                }
+                self.refresh_block_library();
+                Task::none()
+            }
+            Message::WatchedFileChanged(path) => {
+                if path == self.palette_path {
+                    println!("Reloading palette");
+                    self.reload_palette(true);
+                    return Task::none();
+                }
+                let (mut file_changed, mut rom_changed) = (false, false);
+                for file in self.graphics_files.iter() {
+                    match &file.source {
+                        GraphicsFileSource::File(file_path) => file_changed |= *file_path == path,
+                        GraphicsFileSource::Rom(rom_path, _) => rom_changed |= *rom_path == path,
+                        GraphicsFileSource::Unsaved(_) => {}
+                    }
+                }
+                let mut tasks = vec![];
+                if file_changed {
+                    tasks.push(Task::perform(
+                        load_file(path.clone()),
+                        Message::GraphicsFileChangedOnDisk,
                     ));
-                    // Show single block
-                    // self.displayed_block_library = Some(tilemap::Component::new(
-                    //     self.all_graphics_bytes.clone(),
-                    //     Arc::new(file.get_tile_instances().iter().take(4).cloned().collect()),
-                    // ));
-                    // For now start out the displayed block library with the current size
-                    self.displayed_block_library = Some(tilemap::Component::new(
-                        self.all_graphics_bytes.clone(),
-                        Arc::new(Vec::new()),
+                }
+                if rom_changed {
+                    tasks.push(Task::perform(
+                        graphics_file::load_rom(path),
+                        Message::RomChangedOnDisk,
                     ));
                 }
-
-                self.all_graphics_bytes
-                    .write()
-                    .unwrap()
-                    .extend(bytes.iter().cloned());
-                self.graphics_files.push(file);
-
+                Task::batch(tasks)
+            }
+            Message::GraphicsFileChangedOnDisk(Some((path, bytes, compression))) => {
+                let source = GraphicsFileSource::File(path);
+                for file_index in 0..self.graphics_files.len() {
+                    if self.graphics_files[file_index].source == source {
+                        self.reload_graphics_file(file_index, bytes.clone(), compression);
+                    }
+                }
+                self.load_pixel_editor();
+                Task::none()
+            }
+            Message::RomChangedOnDisk(Some((path, rom_graphics_files))) => {
+                for file_index in 0..self.graphics_files.len() {
+                    let GraphicsFileSource::Rom(rom_path, gfx_number) =
+                        &self.graphics_files[file_index].source
+                    else {
+                        continue;
+                    };
+                    if *rom_path != path {
+                        continue;
+                    }
+                    if let Some(rom_graphics_file) = rom_graphics_files
+                        .iter()
+                        .find(|rom_graphics_file| rom_graphics_file.gfx_number == *gfx_number)
+                    {
+                        self.reload_graphics_file(
+                            file_index,
+                            Arc::new(rom_graphics_file.bytes.clone()),
+                            rom_graphics_file.compression,
+                        );
+                    }
+                }
+                self.load_pixel_editor();
                 Task::none()
             }
             Message::DisplayGraphicsFile(file_index) => {
+                self.displayed_graphics_file = Some(file_index);
+                let context = self.palette_context_of_file(file_index);
+                self.palette_selector.set_context(context);
                 let file = self.graphics_files.get(file_index).unwrap();
                 if let Some(displayed_graphics_file_component) =
                     self.displayed_graphics_file_component.as_mut()
                 {
                     displayed_graphics_file_component.set_tile_instances(
                         file.layout_all_tile_instances_from_file(
-                            self.palette_selector.selected_line,
+                            self.palette_selector.get_selected_first_color(),
                         ),
                     );
                 } else {
                     self.displayed_graphics_file_component = Some(tilemap::Component::new(
                         self.all_graphics_bytes.clone(),
+                        self.palette.clone(),
                         file.layout_all_tile_instances_from_file(
-                            self.palette_selector.selected_line,
+                            self.palette_selector.get_selected_first_color(),
                         ),
                     ));
                 }
+                self.load_pixel_editor();
+                Task::none()
+            }
+            Message::SetGraphicsFileBitDepth(file_index, bit_depth) => {
+                self.graphics_files[file_index].bit_depth = bit_depth;
+                self.refresh_block_library();
+                self.update(Message::DisplayGraphicsFile(file_index))
+            }
+            Message::SetGraphicsFileSlot(file_index, slot) => {
+                self.graphics_slots
+                    .retain(|_, bound_file_index| *bound_file_index != file_index);
+                self.graphics_slots.insert(slot, file_index);
+                self.refresh_block_library();
+                Task::none()
+            }
+            Message::OpenGraphicsFiles => {
+                Task::perform(pick_graphics_files(), Message::GraphicsFilesPicked)
+            }
+            Message::GraphicsFilesPicked(paths) => {
+                // Chained rather than batched, so that files are listed in the order they were
+                // picked.
+                paths.into_iter().fold(Task::none(), |task, path| {
+                    task.chain(Task::perform(load_file(path), Message::GraphicsFileLoaded))
+                })
+            }
+            Message::SaveGraphicsFiles => Task::batch(
+                self.graphics_files
+                    .iter()
+                    .filter(|file| file.dirty)
+                    .filter_map(|file| {
+                        let file_id = file.id;
+                        let bytes = Arc::new(
+                            self.all_graphics_bytes.read().unwrap()[file.range_in_all_bytes()]
+                                .to_vec(),
+                        );
+                        let compression = file.compression;
+                        match &file.source {
+                            GraphicsFileSource::File(path) => {
+                                let path = path.clone();
+                                Some(Task::perform(
+                                    async move {
+                                        let saved_bytes =
+                                            save_file(path.clone(), bytes, compression).await?;
+                                        Some((path, saved_bytes))
+                                    },
+                                    move |saved| Message::GraphicsFileSaved(file_id, saved),
+                                ))
+                            }
+                            GraphicsFileSource::Unsaved(file_name) => {
+                                let file_name = file_name.clone();
+                                Some(Task::perform(
+                                    async move {
+                                        let path = pick_graphics_file_to_save(file_name).await?;
+                                        let saved_bytes =
+                                            save_file(path.clone(), bytes, compression).await?;
+                                        Some((path, saved_bytes))
+                                    },
+                                    move |saved| Message::GraphicsFileSaved(file_id, saved),
+                                ))
+                            }
+                            GraphicsFileSource::Rom(..) => {
+                                println!(
+                                    "Can't save {} back into its ROM, use Lunar Magic for that",
+                                    file.source.name()
+                                );
+                                None
+                            }
+                        }
+                    }),
+            ),
+            Message::GraphicsFileSaved(file_id, Some((path, saved_bytes))) => {
+                let all_graphics_bytes = self.all_graphics_bytes.read().unwrap();
+                // The file may have been closed while saving, along with its project.
+                let Some(file) = self
+                    .graphics_files
+                    .iter_mut()
+                    .find(|file| file.id == file_id)
+                else {
+                    println!("Saved {path:?}, which has since been closed");
+                    return Task::none();
+                };
+                println!("Saved {}", file.source.name());
+                // Files that hadn't been saved now have a path.
+                file.source = GraphicsFileSource::File(path);
+                file.bytes = saved_bytes;
+                // Edits made while the file was being saved still need saving.
+                file.dirty = all_graphics_bytes.get(file.range_in_all_bytes())
+                    != Some(file.bytes.as_slice());
                 Task::none()
             }
-            Message::LoadMoreGraphicsFiles => Task::batch([Task::perform(
-                load_file(PathBuf::from(format!(
-                    "{}/assets/anim.bin",
-                    env!("CARGO_MANIFEST_DIR")
-                ))),
-                Message::GraphicsFileLoaded,
-            )]),
             Message::FromDisplayedGraphicsFile(envelope) => {
                 if let Some(displayed_graphics_file_component) =
                     self.displayed_graphics_file_component.as_mut()
@@ -159,6 +436,7 @@ impl App {
                         Some(tilemap::PublicMessage::TileClicked(tile_coords)) => {
                             println!("Selected {tile_coords:?}");
                             displayed_graphics_file_component.set_brush(Some(tile_coords));
+                            self.load_pixel_editor();
                         }
                         None => {}
                     }
@@ -169,79 +447,377 @@ impl App {
                 if let Some(displayed_block_library) = self.displayed_block_library.as_mut() {
                     match displayed_block_library.update(envelope) {
                         Some(tilemap::PublicMessage::TileClicked(clicked_tile_coords)) => {
-                            if let Some(displayed_graphics_file_component) =
-                                self.displayed_graphics_file_component.as_mut()
-                            {
-                                if let Some(brush) = displayed_graphics_file_component.get_brush() {
-                                    println!("Painting {clicked_tile_coords:?} with {brush:?}");
-                                    let mut copy_of_tile_from_graphics_file =
-                                        displayed_graphics_file_component
-                                            .get_tile_instances()
-                                            .iter()
-                                            .find(|tile| tile.get_tile_coords() == brush)
-                                            .unwrap()
-                                            .clone();
-                                    copy_of_tile_from_graphics_file
-                                        .move_to_tile_coords(clicked_tile_coords);
-                                    displayed_block_library.set_tile_instances(Arc::new({
-                                        let mut found = false;
-                                        let mut new_tile_instances_for_block_library =
-                                            displayed_block_library
-                                                .get_tile_instances()
-                                                .iter()
-                                                .cloned()
-                                                .map(|tile_in_block_library| {
-                                                    if tile_in_block_library.get_tile_coords()
-                                                        == clicked_tile_coords
-                                                    {
-                                                        found = true;
-                                                        copy_of_tile_from_graphics_file
-                                                    } else {
-                                                        tile_in_block_library
-                                                    }
-                                                })
-                                                .collect::<Vec<TileInstance>>();
-                                        if !found {
-                                            new_tile_instances_for_block_library
-                                                .push(copy_of_tile_from_graphics_file);
-                                        }
-                                        new_tile_instances_for_block_library
-                                    }));
-                                }
-                            }
+                            self.paint_block_library_tile(clicked_tile_coords);
                         }
                         None => {}
                     }
                 }
                 Task::none()
             }
+            Message::ToggleBrushFlipX => {
+                self.brush_flip_x = !self.brush_flip_x;
+                Task::none()
+            }
+            Message::ToggleBrushFlipY => {
+                self.brush_flip_y = !self.brush_flip_y;
+                Task::none()
+            }
+            Message::SetBrushLayer(brush_layer) => {
+                self.brush_layer = brush_layer;
+                self.brush_priority = self.brush_priority.min(brush_layer.max_priority());
+                Task::none()
+            }
+            Message::SetBrushPriority(brush_priority) => {
+                self.brush_priority = brush_priority;
+                Task::none()
+            }
+            Message::SetBrushScale(brush_scale) => {
+                self.brush_scale = brush_scale;
+                Task::none()
+            }
+            Message::SetHighlightPriority(highlight_priority) => {
+                self.highlight_priority = highlight_priority;
+                if let Some(displayed_block_library) = self.displayed_block_library.as_mut() {
+                    displayed_block_library.set_highlight_priority(highlight_priority);
+                }
+                Task::none()
+            }
             Message::FromPaletteSelector(envelope) => {
                 match self.palette_selector.update(envelope) {
-                    Some(palette::PublicMessage::PaletteLineClicked(line)) => {
-                        println!("PaletteLineClicked({line:?}");
-                        self.palette_selector.selected_line = line;
-
-                        if let Some(displayed_graphics_file_component) =
-                            self.displayed_graphics_file_component.as_mut()
+                    Some(palette::PublicMessage::PaletteColorClicked(line, index)) => {
+                        // Layer 3 tiles use the group of 4 colors that was clicked in.
+                        let group = match self.palette_selector.get_context() {
+                            palette::PaletteContext::Layer3 => index / 4,
+                            palette::PaletteContext::Layer12Bg
+                            | palette::PaletteContext::Sprites => {
+                                self.palette_selector.get_selected_group()
+                            }
+                        };
+                        if (line, group)
+                            != (
+                                self.palette_selector.get_selected_line(),
+                                self.palette_selector.get_selected_group(),
+                            )
+                        {
+                            self.select_palette_line(line, group);
+                        }
+                        let palette_color = line * 16 + index;
+                        if let Some(color) = self.pixel_editor_color_of_palette_color(palette_color)
                         {
-                            displayed_graphics_file_component.set_tile_instances(Arc::new(
-                                displayed_graphics_file_component
-                                    .get_tile_instances()
-                                    .iter()
-                                    .cloned()
-                                    .map(|tile| {
-                                        let mut new_tile = tile.clone();
-                                        new_tile.pal = line as u8;
-                                        new_tile
-                                    })
-                                    .collect::<Vec<TileInstance>>(),
-                            ));
+                            self.pixel_editor.set_primary_color(color);
                         }
+                        self.show_pixel_editor_colors_in_palette();
+                        if self.color_editor.is_open() {
+                            let color = self.palette.read().unwrap()[palette_color];
+                            self.color_editor.open(palette_color, color);
+                        }
+                    }
+                    Some(palette::PublicMessage::PaletteColorRightClicked(line, index)) => {
+                        if let Some(color) =
+                            self.pixel_editor_color_of_palette_color(line * 16 + index)
+                        {
+                            self.pixel_editor.set_secondary_color(color);
+                        }
+                        self.show_pixel_editor_colors_in_palette();
+                    }
+                    Some(palette::PublicMessage::ColorHovered(color_hovered)) => {
+                        self.palette_color_hovered =
+                            color_hovered.map(|(line, index)| line * 16 + index);
                     }
                     None => {}
                 }
                 Task::none()
             }
+            Message::SetPaletteContext(context) => {
+                self.palette_selector.set_context(context);
+                let line = self.palette_selector.get_selected_line();
+                let group = self.palette_selector.get_selected_group();
+                self.select_palette_line(line, group);
+                Task::none()
+            }
+            Message::EditPaletteColor(palette_color) => {
+                let color = self.palette.read().unwrap()[palette_color];
+                self.color_editor.open(palette_color, color);
+                Task::none()
+            }
+            Message::FromColorEditor(envelope) => {
+                match self.color_editor.update(envelope) {
+                    Some(color_editor::PublicMessage::ColorEdited(color_index, color)) => {
+                        self.palette
+                            .write()
+                            .unwrap()
+                            .set_color(color_index, bgr555::to_color(color));
+                        self.show_overriding_colors();
+                        self.load_pixel_editor();
+                    }
+                    None => {}
+                }
+                Task::none()
+            }
+            Message::FromPixelEditor(envelope) => {
+                match self.pixel_editor.update(envelope) {
+                    Some(pixel_editor::PublicMessage::PixelsEdited) => {
+                        self.write_pixel_editor_tiles();
+                    }
+                    Some(pixel_editor::PublicMessage::ColorsPicked) => {
+                        self.show_pixel_editor_colors_in_palette();
+                    }
+                    None => {}
+                }
+                Task::none()
+            }
+            Message::SetPixelEditorEditsQuad(edits_quad) => {
+                self.pixel_editor_edits_quad = edits_quad;
+                self.load_pixel_editor();
+                Task::none()
+            }
+            Message::OpenPalette => Task::perform(
+                palette_file::pick_palette_file(),
+                Message::PaletteFilePicked,
+            ),
+            Message::PaletteFilePicked(Some(path)) => {
+                self.palette_path = path;
+                self.reload_palette(false);
+                Task::none()
+            }
+            Message::ReloadPalette => {
+                self.reload_palette(false);
+                Task::none()
+            }
+            Message::SetPaletteExportFormat(palette_export_format) => {
+                self.palette_export_format = palette_export_format;
+                Task::none()
+            }
+            Message::SetPaletteExportLines(palette_export_lines) => {
+                self.palette_export_lines = palette_export_lines;
+                Task::none()
+            }
+            Message::ExportPalette => {
+                let selected_line = self.palette_selector.get_selected_line();
+                let lines = match self.palette_export_lines {
+                    palette_file::ExportedLines::All => 0..16,
+                    palette_file::ExportedLines::Selected => selected_line..selected_line + 1,
+                    palette_file::ExportedLines::Context => {
+                        self.palette_selector.get_context().selectable_lines()
+                    }
+                };
+                match palette_file::encode(
+                    &self.palette.read().unwrap(),
+                    lines,
+                    self.palette_export_format,
+                ) {
+                    Ok(bytes) => Task::perform(
+                        palette_file::export(bytes, self.palette_export_format),
+                        Message::PaletteExported,
+                    ),
+                    Err(error) => {
+                        println!("Couldn't encode palette: {error}");
+                        Task::none()
+                    }
+                }
+            }
+            Message::PaletteExported(Some(path)) => {
+                println!("Exported palette to {path:?}");
+                Task::none()
+            }
+            Message::SetPngSheetLayout(png_sheet_layout) => {
+                self.png_sheet_layout = png_sheet_layout;
+                Task::none()
+            }
+            Message::SetPngColorZero(png_color_zero) => {
+                self.png_color_zero = png_color_zero;
+                Task::none()
+            }
+            Message::ExportDisplayedGraphicsFilePng => {
+                let Some(displayed_graphics_file) = self.displayed_graphics_file else {
+                    return Task::none();
+                };
+                let file = &self.graphics_files[displayed_graphics_file];
+                let bytes =
+                    self.all_graphics_bytes.read().unwrap()[file.range_in_all_bytes()].to_vec();
+                let file_name = PathBuf::from(file.source.name()).with_extension("png");
+                self.export_png(file_name, &bytes, file.bit_depth)
+            }
+            Message::ExportAllGraphicsPng => {
+                // There's only one bit depth per sheet, so go with the displayed file's.
+                let bit_depth = self
+                    .displayed_graphics_file
+                    .map_or(BitDepth::default(), |file_index| {
+                        self.graphics_files[file_index].bit_depth
+                    });
+                let bytes = self.all_graphics_bytes.read().unwrap().to_vec();
+                self.export_png(PathBuf::from("graphics.png"), &bytes, bit_depth)
+            }
+            Message::PngExported(Some(path)) => {
+                println!("Exported {path:?}");
+                Task::none()
+            }
+            Message::ImportPngAsNewFile => Task::perform(
+                async {
+                    match png_sheet::pick_png_to_import().await {
+                        Some(path) => png_sheet::load(path).await,
+                        None => None,
+                    }
+                },
+                |loaded| Message::PngToImportLoaded(loaded, None),
+            ),
+            Message::ImportPngIntoDisplayedFile => {
+                let displayed_graphics_file = self.displayed_graphics_file;
+                Task::perform(
+                    async {
+                        match png_sheet::pick_png_to_import().await {
+                            Some(path) => png_sheet::load(path).await,
+                            None => None,
+                        }
+                    },
+                    move |loaded| Message::PngToImportLoaded(loaded, displayed_graphics_file),
+                )
+            }
+            Message::PngToImportLoaded(Some((path, image)), file_index) => {
+                self.import_png(&path, &image, file_index)
+            }
+            Message::SetConversionPaletteLines(conversion_palette_lines) => {
+                self.conversion_palette_lines = conversion_palette_lines;
+                Task::none()
+            }
+            Message::ConvertImage => {
+                // Lines past the end of the palette can't be used.
+                let max_palette_lines = self
+                    .conversion_palette_lines
+                    .min(16 - self.palette_selector.get_selected_line());
+                Task::perform(
+                    async move {
+                        match png_sheet::pick_png_to_import().await {
+                            Some(path) => {
+                                color_reduction::load_and_convert(path, max_palette_lines).await
+                            }
+                            None => None,
+                        }
+                    },
+                    Message::ImageConverted,
+                )
+            }
+            Message::ImageConverted(Some((path, conversion))) => {
+                self.add_converted_image(&path, &conversion);
+                Task::none()
+            }
+            Message::SetSnesOutput(snes_output) => {
+                self.palette.write().unwrap().set_output(if snes_output {
+                    palette::ColorOutput::Snes(self.snes_brightness)
+                } else {
+                    palette::ColorOutput::AsIs
+                });
+                self.load_pixel_editor();
+                Task::none()
+            }
+            Message::SetSnesBrightness(snes_brightness) => {
+                self.snes_brightness = snes_brightness;
+                let mut palette = self.palette.write().unwrap();
+                if let palette::ColorOutput::Snes(_) = palette.get_output() {
+                    palette.set_output(palette::ColorOutput::Snes(snes_brightness));
+                }
+                drop(palette);
+                self.load_pixel_editor();
+                Task::none()
+            }
+            Message::FromGradient(envelope) => {
+                match self.gradient.update(envelope) {
+                    Some(gradient::PublicMessage::PreviewChanged) => {
+                        self.show_overriding_colors();
+                    }
+                    Some(gradient::PublicMessage::GradientApplied(colors)) => {
+                        let mut palette = self.palette.write().unwrap();
+                        for (color_index, color) in colors {
+                            palette.set_color(color_index, bgr555::to_color(color));
+                        }
+                        drop(palette);
+                        self.show_overriding_colors();
+                        self.load_pixel_editor();
+                    }
+                    None => {}
+                }
+                Task::none()
+            }
+            Message::FromPaletteAnimations(envelope) => {
+                match self.palette_animations.update(envelope) {
+                    Some(palette_animation::PublicMessage::OverridingColorsChanged) => {
+                        self.show_overriding_colors();
+                    }
+                    None => {}
+                }
+                Task::none()
+            }
+            Message::NewProject => {
+                self.close_project();
+                self.reload_palette(false);
+                Task::none()
+            }
+            Message::OpenProject => Task::perform(
+                async {
+                    match project::pick_project_to_open().await {
+                        Some(path) => project::open(path).await,
+                        None => None,
+                    }
+                },
+                Message::ProjectOpened,
+            ),
+            Message::ProjectOpened(Some(opened_project)) => {
+                self.close_project();
+                self.project_path = Some(opened_project.path.clone());
+                self.palette_path = opened_project.project.palette.clone();
+                self.palette_selector
+                    .set_selected_line(opened_project.project.palette_line);
+                self.palette_selector
+                    .set_selected_group(opened_project.project.palette_group);
+                for (project_graphics_file, bytes, compression) in
+                    opened_project.graphics_files.iter()
+                {
+                    self.add_graphics_file(
+                        project_graphics_file.source(),
+                        bytes.clone(),
+                        *compression,
+                        project_graphics_file.bit_depth,
+                    );
+                    let file_index = self.graphics_files.len() - 1;
+                    if let Some(slot) = project_graphics_file.slot {
+                        self.graphics_slots.insert(slot, file_index);
+                    }
+                }
+                self.block_library_tiles = opened_project.project.block_library.clone();
+                self.refresh_block_library();
+                self.palette_animations
+                    .set_animations(opened_project.project.palette_animations.clone());
+                self.reload_palette(false);
+                if let Some(displayed_graphics_file) = self.displayed_graphics_file {
+                    self.update(Message::DisplayGraphicsFile(displayed_graphics_file))
+                } else {
+                    Task::none()
+                }
+            }
+            Message::SaveProject => match self.project_path.clone() {
+                Some(path) => Task::perform(
+                    project::save(path, self.to_project()),
+                    Message::ProjectSaved,
+                ),
+                None => self.update(Message::SaveProjectAs),
+            },
+            Message::SaveProjectAs => {
+                let project = self.to_project();
+                Task::perform(
+                    async {
+                        match project::pick_project_to_save().await {
+                            Some(path) => project::save(path, project).await,
+                            None => None,
+                        }
+                    },
+                    Message::ProjectSaved,
+                )
+            }
+            Message::ProjectSaved(Some(path)) => {
+                println!("Saved project to {path:?}");
+                self.project_path = Some(path);
+                Task::none()
+            }
             Message::MouseMovedOverPalette(point) => {
                 println!("Moved in palette {:?}", point);
                 Task::none()
@@ -254,13 +830,822 @@ impl App {
         }
     }
 
-    fn view(&self) -> Element<Message> {
+    /// Exports graphics as a PNG sheet in the selected palette line, asking where to save it.
+    fn export_png(&self, file_name: PathBuf, bytes: &[u8], bit_depth: BitDepth) -> Task<Message> {
+        let pal =
+            bit_depth.pal_for_first_palette_color(self.palette_selector.get_selected_first_color());
+        match png_sheet::encode(
+            bytes,
+            bit_depth,
+            self.png_sheet_layout,
+            &self.palette.read().unwrap().displayed_colors(),
+            bit_depth.first_palette_color(pal),
+            self.png_color_zero,
+        ) {
+            Ok(png_bytes) => Task::perform(
+                png_sheet::export(file_name.to_string_lossy().into_owned(), png_bytes),
+                Message::PngExported,
+            ),
+            Err(error) => {
+                println!("Couldn't encode PNG: {error}");
+                Task::none()
+            }
+        }
+    }
+
+    /// Imports a PNG sheet in the selected layout and palette line. Importing into an existing file
+    /// overwrites its tiles from the start, at its bit depth, while a new file is 4bpp and only
+    /// gets written to disk once the graphics are saved.
+    fn import_png(
+        &mut self,
+        path: &Path,
+        image: &image::RgbaImage,
+        file_index: Option<usize>,
+    ) -> Task<Message> {
+        let bit_depth = file_index.map_or(BitDepth::Bpp4, |file_index| {
+            self.graphics_files[file_index].bit_depth
+        });
+        let pal =
+            bit_depth.pal_for_first_palette_color(self.palette_selector.get_selected_first_color());
+        let (bytes, report) = png_sheet::decode(
+            image,
+            bit_depth,
+            self.png_sheet_layout,
+            // The same colors as exporting uses, so that exported sheets import back the same.
+            &self.palette.read().unwrap().displayed_colors(),
+            bit_depth.first_palette_color(pal),
+        );
+        println!("Imported {path:?}: {report}");
+        self.import_warning = (report.inexact_pixel_count > 0).then(|| {
+            format!(
+                "Imported {}: {report}",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            )
+        });
+
+        match file_index {
+            Some(file_index) => {
+                let file = &mut self.graphics_files[file_index];
+                if bytes.len() > file.bytes.len() {
+                    println!(
+                        "Only the first {} tiles fit in {}",
+                        file.tile_count(),
+                        file.source.name()
+                    );
+                }
+                let len = bytes.len().min(file.bytes.len());
+                self.all_graphics_bytes
+                    .write()
+                    .unwrap()
+                    .write(file.offset_in_all_bytes, &bytes[..len]);
+                file.dirty = true;
+                self.load_pixel_editor();
+                Task::none()
+            }
+            None => {
+                self.add_graphics_file(
+                    GraphicsFileSource::unsaved_from_image(path),
+                    Arc::new(bytes),
+                    lz::Compression::None,
+                    bit_depth,
+                );
+                self.graphics_files.last_mut().unwrap().dirty = true;
+                self.bind_to_free_slot(self.graphics_files.len() - 1);
+                self.refresh_block_library();
+                self.update(Message::DisplayGraphicsFile(self.graphics_files.len() - 1))
+            }
+        }
+    }
+
+    /// Adds a converted image's tiles as a new 4bpp file in the first free slot, writes its palette
+    /// lines over the palette starting at the selected line, and paints it into the block library
+    /// from the top left corner for review. Like an imported PNG, the file only gets written to
+    /// disk once the graphics are saved, and the palette isn't saved at all.
+    fn add_converted_image(&mut self, path: &Path, conversion: &color_reduction::Conversion) {
+        // Tiles past the end of a slot would have tile numbers of the next slot's tiles.
+        if conversion.tile_count() > GraphicsSlot::TILES_PER_SLOT {
+            let error = format!(
+                "{} has {} different tiles, more than the {} that fit in a GFX slot",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                conversion.tile_count(),
+                GraphicsSlot::TILES_PER_SLOT
+            );
+            println!("{error}");
+            self.conversion_error = Some(error);
+            return;
+        }
+        // Checked before anything changes, so that a conversion that can't be shown leaves the
+        // palette and files as they were.
+        let Some(slot) = GraphicsSlot::ALL
+            .into_iter()
+            .find(|slot| !self.graphics_slots.contains_key(slot))
+        else {
+            let error = "There's no free GFX slot to show the converted image in".to_string();
+            println!("{error}");
+            self.conversion_error = Some(error);
+            return;
+        };
+        let first_palette_line = self.palette_selector.get_selected_line();
+        let palette_line_count = self.palette.read().unwrap().len() / 16;
+        if first_palette_line + conversion.palette_lines.len() > palette_line_count {
+            let error = format!(
+                "{} needs {} palette lines, but only {} fit from the selected line {:X}",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                conversion.palette_lines.len(),
+                palette_line_count - first_palette_line,
+                first_palette_line
+            );
+            println!("{error}");
+            self.conversion_error = Some(error);
+            return;
+        }
+        self.conversion_error = None;
+
+        let mut palette = self.palette.write().unwrap();
+        for (line_index, line) in conversion.palette_lines.iter().enumerate() {
+            let first_color = (first_palette_line + line_index) * 16 + 1;
+            for (color_index, color) in line.iter().enumerate() {
+                palette.set_color(first_color + color_index, bgr555::to_color(*color));
+            }
+        }
+        drop(palette);
+        self.load_pixel_editor();
+
+        self.add_graphics_file(
+            GraphicsFileSource::unsaved_from_image(path),
+            Arc::new(conversion.graphics_bytes.clone()),
+            lz::Compression::None,
+            BitDepth::Bpp4,
+        );
+        let file_index = self.graphics_files.len() - 1;
+        let file = &mut self.graphics_files[file_index];
+        file.dirty = true;
+        println!(
+            "Converted {path:?} into {} tiles using {} palette lines",
+            file.tile_count(),
+            conversion.palette_lines.len()
+        );
+
+        self.graphics_slots.insert(slot, file_index);
+        for tile_instance in conversion.tile_instances.iter() {
+            let mut tile_instance = *tile_instance;
+            tile_instance.pal += first_palette_line as u8;
+            let painted_tile = BlockLibraryTile {
+                slot,
+                tile_instance,
+            };
+            match self
+                .block_library_tiles
+                .iter_mut()
+                .find(|tile_in_block_library| {
+                    tile_in_block_library.tile_instance.get_tile_coords()
+                        == tile_instance.get_tile_coords()
+                        && tile_in_block_library.tile_instance.get_layer()
+                            == tile_instance.get_layer()
+                }) {
+                Some(tile_in_block_library) => *tile_in_block_library = painted_tile,
+                None => self.block_library_tiles.push(painted_tile),
+            }
+        }
+        self.refresh_block_library();
+    }
+
+    /// Watches every open file for changes made by other programs, listens for the brush's flip
+    /// keys, and steps the palette animations every frame while they're playing.
+    fn subscription(&self) -> Subscription<Message> {
+        let mut watched_paths = self
+            .graphics_files
+            .iter()
+            .filter_map(|file| match &file.source {
+                GraphicsFileSource::File(path) | GraphicsFileSource::Rom(path, _) => {
+                    Some(path.clone())
+                }
+                GraphicsFileSource::Unsaved(_) => None,
+            })
+            .collect::<Vec<_>>();
+        watched_paths.push(self.palette_path.clone());
+        watched_paths.sort();
+        watched_paths.dedup();
+        let subscriptions = [
+            file_watcher::watch(watched_paths).map(Message::WatchedFileChanged),
+            // Keys typed into text fields are captured by them, so they don't flip the brush.
+            keyboard::on_key_press(|key, modifiers| match key.as_ref() {
+                keyboard::Key::Character("x") if modifiers.is_empty() => {
+                    Some(Message::ToggleBrushFlipX)
+                }
+                keyboard::Key::Character("y") if modifiers.is_empty() => {
+                    Some(Message::ToggleBrushFlipY)
+                }
+                _ => None,
+            }),
+            self.palette_animations
+                .subscription()
+                .map(Message::FromPaletteAnimations),
+        ];
+        Subscription::batch(subscriptions)
+    }
+
+    /// Shows each palette animation's color on the frame shown in the shared palette, and the
+    /// gradient if it's previewed, in place of the palette's own colors.
+    fn show_overriding_colors(&mut self) {
+        let overriding_colors = self
+            .palette_animations
+            .get_overriding_colors()
+            .into_iter()
+            .chain(self.gradient.get_previewed_colors())
+            .map(|(color_index, color)| (color_index, bgr555::to_color(color)))
+            .collect();
+        self.palette
+            .write()
+            .unwrap()
+            .set_overriding_colors(overriding_colors);
+    }
+
+    /// Forgets every graphics file and the block library, leaving an empty project.
+    fn close_project(&mut self) {
+        self.project_path = None;
+        self.displayed_graphics_file_component = None;
+        self.displayed_graphics_file = None;
+        self.graphics_files.clear();
+        self.graphics_slots.clear();
+        self.all_graphics_bytes.write().unwrap().clear();
+        self.displayed_block_library = None;
+        self.block_library_tiles.clear();
+        self.pixel_editor_target = None;
+        self.palette_animations.set_animations(vec![]);
+        self.gradient.clear_anchors();
+        self.show_overriding_colors();
+    }
+
+    fn to_project(&self) -> project::Project {
+        project::Project {
+            palette: self.palette_path.clone(),
+            palette_line: self.palette_selector.get_selected_line(),
+            palette_group: self.palette_selector.get_selected_group(),
+            palette_animations: self.palette_animations.get_animations().to_vec(),
+            graphics_files: self
+                .graphics_files
+                .iter()
+                .enumerate()
+                .filter_map(|(file_index, file)| {
+                    let (path, gfx_number_in_rom) = match &file.source {
+                        GraphicsFileSource::File(path) => (path.clone(), None),
+                        GraphicsFileSource::Rom(path, gfx_number) => {
+                            (path.clone(), Some(*gfx_number))
+                        }
+                        GraphicsFileSource::Unsaved(file_name) => {
+                            println!(
+                                "{file_name} hasn't been saved yet, so it's left out of the \
+                                 project"
+                            );
+                            return None;
+                        }
+                    };
+                    Some(project::ProjectGraphicsFile {
+                        path,
+                        gfx_number_in_rom,
+                        bit_depth: file.bit_depth,
+                        slot: self.slot_of_file(file_index),
+                    })
+                })
+                .collect(),
+            block_library: self.block_library_tiles.clone(),
+        }
+    }
+
+    /// Copies the brush tile from the displayed graphics file into the block library. The copy
+    /// remembers its tile by slot, so the displayed file has to be bound to one.
+    fn paint_block_library_tile(&mut self, clicked_tile_coords: TileCoords) {
+        let (Some(displayed_graphics_file_component), Some(displayed_graphics_file)) = (
+            self.displayed_graphics_file_component.as_ref(),
+            self.displayed_graphics_file,
+        ) else {
+            return;
+        };
+        let Some(brush) = displayed_graphics_file_component.get_brush() else {
+            return;
+        };
+        let file = &self.graphics_files[displayed_graphics_file];
+        let Some(slot) = self.slot_of_file(displayed_graphics_file) else {
+            println!(
+                "{} isn't bound to a GFX slot, so its tiles can't be painted",
+                file.source.name()
+            );
+            return;
+        };
+
+        let Some(mut tile_instance) = displayed_graphics_file_component
+            .get_tile_instances()
+            .iter()
+            .find(|tile| tile.get_tile_coords() == brush)
+            .copied()
+        else {
+            return;
+        };
+        tile_instance.id -= file.first_tile_id();
+        tile_instance.move_to_tile_coords(clicked_tile_coords);
+        tile_instance.set_flip_x(self.brush_flip_x);
+        tile_instance.set_flip_y(self.brush_flip_y);
+        tile_instance.set_layer(self.brush_layer);
+        tile_instance.set_priority(self.brush_priority);
+        tile_instance.scale = self.brush_scale;
+        if tile_instance.id >= GraphicsSlot::TILES_PER_SLOT {
+            println!("{brush:?} is past the end of the {slot} slot");
+            return;
+        }
+        println!(
+            "Painting {clicked_tile_coords:?} with tile {:03X}{}{}",
+            slot.first_tile_number() + tile_instance.id,
+            if tile_instance.get_flip_x() {
+                ", flipped X"
+            } else {
+                ""
+            },
+            if tile_instance.get_flip_y() {
+                ", flipped Y"
+            } else {
+                ""
+            },
+        );
+
+        let painted_tile = BlockLibraryTile {
+            slot,
+            tile_instance,
+        };
+        match self
+            .block_library_tiles
+            .iter_mut()
+            .find(|tile_in_block_library| {
+                // Each layer has its own tile at each spot, so that layers can be stacked.
+                tile_in_block_library.tile_instance.get_tile_coords() == clicked_tile_coords
+                    && tile_in_block_library.tile_instance.get_layer() == self.brush_layer
+            }) {
+            Some(tile_in_block_library) => *tile_in_block_library = painted_tile,
+            None => self.block_library_tiles.push(painted_tile),
+        }
+        self.refresh_block_library();
+    }
+
+    /// Points the block library's tiles at wherever their slots' files are in the all-bytes array.
+    /// Tiles in slots without a file aren't shown.
+    fn refresh_block_library(&mut self) {
+        if let Some(displayed_block_library) = self.displayed_block_library.as_mut() {
+            displayed_block_library.set_tile_instances(Arc::new(
+                self.block_library_tiles
+                    .iter()
+                    .filter_map(|tile| tile.resolve(&self.graphics_slots, &self.graphics_files))
+                    .collect(),
+            ));
+        }
+    }
+
+    /// Guesses which palette lines a file's tiles use from its bit depth and slot.
+    fn palette_context_of_file(&self, file_index: usize) -> palette::PaletteContext {
+        match (
+            self.graphics_files[file_index].bit_depth,
+            self.slot_of_file(file_index),
+        ) {
+            (BitDepth::Bpp2, _) => palette::PaletteContext::Layer3,
+            (
+                _,
+                Some(GraphicsSlot::Sp1 | GraphicsSlot::Sp2 | GraphicsSlot::Sp3 | GraphicsSlot::Sp4),
+            ) => palette::PaletteContext::Sprites,
+            _ => palette::PaletteContext::Layer12Bg,
+        }
+    }
+
+    /// Shows the displayed graphics file's tiles in another palette line, and for layer 3, in
+    /// another group of 4 colors in it.
+    fn select_palette_line(&mut self, line: usize, group: usize) {
+        println!("Selecting palette line {line:X}, group {group}");
+        self.palette_selector.set_selected_line(line);
+        self.palette_selector.set_selected_group(group);
+        let first_color = self.palette_selector.get_selected_first_color();
+
+        if let Some(displayed_graphics_file_component) =
+            self.displayed_graphics_file_component.as_mut()
+        {
+            displayed_graphics_file_component.set_tile_instances(Arc::new(
+                displayed_graphics_file_component
+                    .get_tile_instances()
+                    .iter()
+                    .cloned()
+                    .map(|tile| {
+                        let mut new_tile = tile;
+                        new_tile.set_first_palette_color(first_color);
+                        new_tile
+                    })
+                    .collect::<Vec<TileInstance>>(),
+            ));
+        }
+        self.load_pixel_editor();
+    }
+
+    /// Shows the displayed graphics file's brush tile in the pixel editor, or the quad of tiles
+    /// around it.
+    fn load_pixel_editor(&mut self) {
+        self.pixel_editor_target = None;
+        let Some(displayed_graphics_file_component) =
+            self.displayed_graphics_file_component.as_ref()
+        else {
+            return;
+        };
+        let Some(brush) = displayed_graphics_file_component.get_brush() else {
+            return;
+        };
+        let (origin, tiles_across) = if self.pixel_editor_edits_quad {
+            (TileCoords(brush.0 & !1, brush.1 & !1), 2)
+        } else {
+            (brush, 1)
+        };
+        let tile_instances = displayed_graphics_file_component.get_tile_instances();
+        let tiles = (0..tiles_across)
+            .flat_map(|y| (0..tiles_across).map(move |x| TileCoords(x, y)))
+            .filter_map(|offset| {
+                let tile_coords = TileCoords(origin.0 + offset.0, origin.1 + offset.1);
+                tile_instances
+                    .iter()
+                    .find(|tile| tile.get_tile_coords() == tile_coords)
+                    .map(|tile| (*tile, offset))
+            })
+            .collect::<Vec<_>>();
+        let Some((first_tile, _)) = tiles.first() else {
+            return;
+        };
+        let bit_depth = first_tile.get_bit_depth();
+        let first_color = bit_depth.first_palette_color(first_tile.pal);
+
+        let width = tiles_across as usize * planar::PIXELS_PER_TILE_ROW;
+        let mut pixels = vec![0; width * width];
+        let all_graphics_bytes = self.all_graphics_bytes.read().unwrap();
+        for (tile, offset) in tiles.iter() {
+            let tile_start = tile.id as usize * bit_depth.bytes_per_tile();
+            let tile_pixels = planar::decode_tile(
+                all_graphics_bytes.get(tile_start..).unwrap_or_default(),
+                bit_depth,
+            );
+            for (row, row_pixels) in tile_pixels.chunks(planar::PIXELS_PER_TILE_ROW).enumerate() {
+                let start = (offset.1 as usize * planar::PIXELS_PER_TILE_ROW + row) * width
+                    + offset.0 as usize * planar::PIXELS_PER_TILE_ROW;
+                pixels[start..start + planar::PIXELS_PER_TILE_ROW].copy_from_slice(row_pixels);
+            }
+        }
+        drop(all_graphics_bytes);
+
+        let palette = self.palette.read().unwrap().displayed_colors();
+        let colors = (0..1usize << bit_depth.bits_per_pixel())
+            .map(|color_index| {
+                palette
+                    .get(first_color + color_index)
+                    .copied()
+                    .unwrap_or(Color::BLACK)
+            })
+            .collect();
+        // 8bpp tiles can use the whole palette, but only offer the selected line's colors.
+        let swatches = match bit_depth {
+            BitDepth::Bpp8 => {
+                let first_swatch = self.palette_selector.get_selected_line() * 16;
+                (first_swatch..first_swatch + 16)
+                    .map(|color| color as u8)
+                    .collect()
+            }
+            _ => (0..1 << bit_depth.bits_per_pixel()).collect(),
+        };
+
+        self.pixel_editor.set_pixels(width, width, pixels);
+        self.pixel_editor.set_colors(colors, swatches);
+        self.pixel_editor_target = Some(PixelEditorTarget {
+            bit_depth,
+            first_color,
+            tiles: tiles
+                .into_iter()
+                .map(|(tile, offset)| (tile.id, offset))
+                .collect(),
+        });
+        self.show_pixel_editor_colors_in_palette();
+    }
+
+    /// The pixel editor's color index for a color in the palette, if its tiles can use it.
+    fn pixel_editor_color_of_palette_color(&self, palette_color: usize) -> Option<u8> {
+        let pixel_editor_target = self.pixel_editor_target.as_ref()?;
+        let color = palette_color.checked_sub(pixel_editor_target.first_color)?;
+        (color < 1 << pixel_editor_target.bit_depth.bits_per_pixel()).then_some(color as u8)
+    }
+
+    /// Highlights the colors the pixel editor draws with in the palette.
+    fn show_pixel_editor_colors_in_palette(&mut self) {
+        let Some(pixel_editor_target) = self.pixel_editor_target.as_ref() else {
+            return;
+        };
+        let first_color = pixel_editor_target.first_color;
+        let (primary_color, secondary_color) = self.pixel_editor.get_colors();
+        self.palette_selector.set_selected_colors(
+            Some(first_color + primary_color as usize),
+            Some(first_color + secondary_color as usize),
+        );
+    }
+
+    /// Encodes the pixel editor's pixels back into the tiles it's showing. Every tilemap showing
+    /// those tiles picks up the change when it next draws.
+    fn write_pixel_editor_tiles(&mut self) {
+        let Some(pixel_editor_target) = self.pixel_editor_target.as_ref() else {
+            return;
+        };
+        let bit_depth = pixel_editor_target.bit_depth;
+        let pixels = self.pixel_editor.get_pixels();
+        let width = self.pixel_editor.get_width();
+        let mut all_graphics_bytes = self.all_graphics_bytes.write().unwrap();
+        for (tile_id, offset) in pixel_editor_target.tiles.iter() {
+            let mut tile_pixels = [0; planar::PIXELS_PER_TILE];
+            for (row, row_pixels) in tile_pixels
+                .chunks_mut(planar::PIXELS_PER_TILE_ROW)
+                .enumerate()
+            {
+                let start = (offset.1 as usize * planar::PIXELS_PER_TILE_ROW + row) * width
+                    + offset.0 as usize * planar::PIXELS_PER_TILE_ROW;
+                row_pixels.copy_from_slice(&pixels[start..start + planar::PIXELS_PER_TILE_ROW]);
+            }
+            let tile_bytes = planar::encode_tile(&tile_pixels, bit_depth);
+            // The last tile of the last file may be cut short.
+            let tile_start = *tile_id as usize * bit_depth.bytes_per_tile();
+            let len = tile_bytes
+                .len()
+                .min(all_graphics_bytes.len().saturating_sub(tile_start));
+            all_graphics_bytes.write(tile_start, &tile_bytes[..len]);
+
+            let tile_range = tile_start..tile_start + len;
+            for file in self.graphics_files.iter_mut() {
+                let file_range = file.range_in_all_bytes();
+                if file_range.start < tile_range.end && tile_range.start < file_range.end {
+                    file.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Puts a graphics file's new contents on disk into its place in the all-bytes array, which
+    /// every tilemap picks up when it next draws. Files with unsaved edits keep them, and files
+    /// whose size changed no longer fit in their place, so both are only warned about.
+    fn reload_graphics_file(
+        &mut self,
+        file_index: usize,
+        bytes: Arc<Vec<u8>>,
+        compression: lz::Compression,
+    ) {
+        let file = &mut self.graphics_files[file_index];
+        let mut all_graphics_bytes = self.all_graphics_bytes.write().unwrap();
+        match file.change_on_disk(all_graphics_bytes.get(file.range_in_all_bytes()), &bytes) {
+            ChangeOnDisk::AlreadyShown => {
+                file.bytes = bytes;
+                file.dirty = false;
+                file.size_conflict = None;
+            }
+            ChangeOnDisk::SizeConflict(size_on_disk) => {
+                println!(
+                    "{} changed size on disk from {:#X} to {:#X} bytes, reopen it to see the changes",
+                    file.source.name(),
+                    file.bytes.len(),
+                    size_on_disk
+                );
+                file.size_conflict = Some(size_on_disk);
+            }
+            ChangeOnDisk::KeepEdits => {
+                println!(
+                    "{} changed on disk, but has unsaved edits, so it was left as it is",
+                    file.source.name()
+                );
+            }
+            ChangeOnDisk::Reload => {
+                println!("Reloading {}", file.source.name());
+                all_graphics_bytes.write(file.offset_in_all_bytes, &bytes);
+                file.bytes = bytes;
+                file.compression = compression;
+                file.size_conflict = None;
+            }
+        }
+    }
+
+    /// Reads the palette from `palette_path`. If it can't be read, the palette stays as it was and
+    /// the error is shown under it. With `keep_edits`, a palette with unsaved edits is also left as
+    /// it is, and the conflict is shown instead.
+    fn reload_palette(&mut self, keep_edits: bool) {
+        match palette_file::load(&self.palette_path) {
+            Ok(palette_colors) => {
+                self.palette_error = None;
+                let mut palette = self.palette.write().unwrap();
+                // This is also how the palette comes back when it's exported over its own file.
+                if keep_edits && palette.is_dirty() && !palette.has_colors(&palette_colors) {
+                    println!("The palette changed on disk, but has unsaved edits, so it was kept");
+                    self.palette_conflict = true;
+                    return;
+                }
+                palette.set_colors(palette_colors);
+                drop(palette);
+                self.palette_conflict = false;
+                self.show_overriding_colors();
+                self.load_pixel_editor();
+            }
+            Err(error) => {
+                println!("{error}");
+                self.palette_error = Some(error);
+            }
+        }
+    }
+
+    fn slot_of_file(&self, file_index: usize) -> Option<GraphicsSlot> {
+        self.graphics_slots
+            .iter()
+            .find(|(_, bound_file_index)| **bound_file_index == file_index)
+            .map(|(slot, _)| *slot)
+    }
+
+    /// Binds a newly added file to the first slot that no file is bound to yet, so that files fill
+    /// the slots in the order they're added. The slot pick list can move it elsewhere afterwards.
+    fn bind_to_free_slot(&mut self, file_index: usize) -> Option<GraphicsSlot> {
+        let slot = GraphicsSlot::ALL
+            .into_iter()
+            .find(|slot| !self.graphics_slots.contains_key(slot))?;
+        self.graphics_slots.insert(slot, file_index);
+        Some(slot)
+    }
+
+    /// Adds a file at the given bit depth, which its tiles are laid out with straight away if it's
+    /// the first file.
+    fn add_graphics_file(
+        &mut self,
+        source: GraphicsFileSource,
+        bytes: Arc<Vec<u8>>,
+        compression: lz::Compression,
+        bit_depth: BitDepth,
+    ) {
+        // Pad so that the file starts on a whole tile, whatever bit depth it ends up being
+        // displayed with.
+        let offset_in_all_bytes = self
+            .all_graphics_bytes
+            .write()
+            .unwrap()
+            .append(GraphicsFile::ALIGNMENT_IN_ALL_BYTES, &bytes);
+
+        let file = GraphicsFile {
+            id: self.next_graphics_file_id,
+            source,
+            bit_depth,
+            bytes,
+            offset_in_all_bytes,
+            compression,
+            dirty: false,
+            size_conflict: None,
+        };
+        self.next_graphics_file_id += 1;
+
+        if self.displayed_graphics_file_component.is_none() {
+            self.displayed_graphics_file = Some(self.graphics_files.len());
+            self.displayed_graphics_file_component = Some(tilemap::Component::new(
+                self.all_graphics_bytes.clone(),
+                self.palette.clone(),
+                file.layout_all_tile_instances_from_file(
+                    self.palette_selector.get_selected_first_color(),
+                ),
+            ));
+            // Show single block
+            // self.displayed_block_library = Some(tilemap::Component::new(
+            //     self.all_graphics_bytes.clone(),
+            //     Arc::new(file.get_tile_instances().iter().take(4).cloned().collect()),
+            // ));
+            // For now start out the displayed block library with the current size
+            let mut displayed_block_library = tilemap::Component::new(
+                self.all_graphics_bytes.clone(),
+                self.palette.clone(),
+                Arc::new(Vec::new()),
+            );
+            displayed_block_library.set_highlight_priority(self.highlight_priority);
+            self.displayed_block_library = Some(displayed_block_library);
+        }
+
+        self.graphics_files.push(file);
+    }
+
+    fn view(&self) -> Element<'_, Message> {
         use iced::widget::{column, row, *};
         let heading = |label| container(label).padding(10);
-        container(
+        container(column![
+            row![
+                button("New").on_press(Message::NewProject),
+                button("Open…").on_press(Message::OpenProject),
+                button("Save").on_press(Message::SaveProject),
+                button("Save As…").on_press(Message::SaveProjectAs),
+            ]
+            .spacing(10)
+            .padding(10),
+            row![
+                text("PNG sheets"),
+                pick_list(
+                    png_sheet::SheetLayout::ALL,
+                    Some(self.png_sheet_layout),
+                    Message::SetPngSheetLayout
+                ),
+                pick_list(
+                    png_sheet::ColorZero::ALL,
+                    Some(self.png_color_zero),
+                    Message::SetPngColorZero
+                ),
+                button("Export PNG…").on_press_maybe(
+                    self.displayed_graphics_file
+                        .map(|_| Message::ExportDisplayedGraphicsFilePng)
+                ),
+                button("Export all as PNG…").on_press_maybe(
+                    (!self.graphics_files.is_empty()).then_some(Message::ExportAllGraphicsPng)
+                ),
+                button("Import PNG…").on_press(Message::ImportPngAsNewFile),
+                button("Import PNG into file…").on_press_maybe(
+                    self.displayed_graphics_file
+                        .map(|_| Message::ImportPngIntoDisplayedFile)
+                ),
+            ]
+            .push_maybe(
+                self.import_warning
+                    .as_ref()
+                    .map(|warning| text(warning).style(text::danger))
+            )
+            .align_y(Alignment::Center)
+            .spacing(10)
+            .padding(10),
+            row![
+                text("Image conversion"),
+                pick_list(
+                    (1..=8).collect::<Vec<usize>>(),
+                    Some(self.conversion_palette_lines),
+                    Message::SetConversionPaletteLines
+                ),
+                text("palette lines"),
+                button("Convert image…").on_press(Message::ConvertImage),
+            ]
+            .push_maybe(
+                self.conversion_error
+                    .as_ref()
+                    .map(|error| text(error).style(text::danger))
+            )
+            .align_y(Alignment::Center)
+            .spacing(10)
+            .padding(10),
+            row![
+                text("Palette export"),
+                pick_list(
+                    palette_file::ExportedLines::ALL,
+                    Some(self.palette_export_lines),
+                    Message::SetPaletteExportLines
+                ),
+                pick_list(
+                    palette_file::ExportFormat::ALL,
+                    Some(self.palette_export_format),
+                    Message::SetPaletteExportFormat
+                ),
+                button("Export palette…").on_press(Message::ExportPalette),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(10)
+            .padding(10),
+            horizontal_rule(2),
             row![
                 column![
                     heading("Block Library"),
+                    row![
+                        checkbox("Flip X", self.brush_flip_x)
+                            .on_toggle(|_| Message::ToggleBrushFlipX),
+                        checkbox("Flip Y", self.brush_flip_y)
+                            .on_toggle(|_| Message::ToggleBrushFlipY),
+                        pick_list(
+                            tilemap::Layer::ALL,
+                            Some(self.brush_layer),
+                            Message::SetBrushLayer
+                        ),
+                        text("Priority"),
+                        pick_list(
+                            (0..=self.brush_layer.max_priority()).collect::<Vec<u8>>(),
+                            Some(self.brush_priority),
+                            Message::SetBrushPriority
+                        ),
+                        text("Scale"),
+                        pick_list([1, 2, 4], Some(self.brush_scale), Message::SetBrushScale),
+                        checkbox("Highlight high priority", self.highlight_priority)
+                            .on_toggle(Message::SetHighlightPriority),
+                    ]
+                    .spacing(10)
+                    .align_y(Alignment::Center),
+                    // Painting needs the tiles' slot, so say why clicking does nothing without one.
+                    match self
+                        .displayed_graphics_file
+                        .filter(|file_index| self.slot_of_file(*file_index).is_none())
+                    {
+                        Some(file_index) => container(
+                            text(format!(
+                                "Pick a GFX slot for {} to paint its tiles",
+                                self.graphics_files[file_index].source.name()
+                            ))
+                            .style(text::danger)
+                        ),
+                        None => container(column![]),
+                    },
                     Space::with_height(Length::FillPortion(1)),
                     self.displayed_block_library.as_ref().map_or_else(
                         || container(column![]),
@@ -271,9 +1656,76 @@ impl App {
                     ),
                     Space::with_height(Length::FillPortion(1)),
                     horizontal_rule(2),
-                    heading("Palette"),
+                    heading(match self.palette.read().unwrap().is_dirty() {
+                        true => "Palette *",
+                        false => "Palette",
+                    }),
+                    row![
+                        pick_list(
+                            palette::PaletteContext::ALL,
+                            Some(self.palette_selector.get_context()),
+                            Message::SetPaletteContext
+                        ),
+                        button("Open palette…").on_press(Message::OpenPalette),
+                        button("Edit color").on_press_maybe(
+                            self.palette_selector
+                                .get_selected_color()
+                                .map(Message::EditPaletteColor)
+                        ),
+                    ]
+                    .push_maybe(self.palette_color_hovered.map(|palette_color| {
+                        let [r, g, b, _] = self.palette.read().unwrap()[palette_color].into_rgba8();
+                        text(format!(
+                            "Color {:X}:{:X} ${:04X}",
+                            palette_color / 16,
+                            palette_color % 16,
+                            bgr555::from_rgb8([r, g, b])
+                        ))
+                    }))
+                    .push_maybe(
+                        self.palette_error
+                            .as_ref()
+                            .map(|error| { text(error.to_string()).style(text::danger) })
+                    )
+                    .push_maybe(self.palette_conflict.then(|| {
+                        row![
+                            text("Changed on disk, unsaved edits kept").style(text::danger),
+                            button("Reload").on_press(Message::ReloadPalette),
+                        ]
+                        .spacing(10)
+                        .align_y(Alignment::Center)
+                    }))
+                    .spacing(10)
+                    .padding(10)
+                    .align_y(Alignment::Center),
+                    row![
+                        checkbox(
+                            "SNES output",
+                            self.palette.read().unwrap().get_output() != palette::ColorOutput::AsIs
+                        )
+                        .on_toggle(Message::SetSnesOutput),
+                        text("Brightness"),
+                        slider(0..=15, self.snes_brightness, Message::SetSnesBrightness).width(120),
+                        text(self.snes_brightness),
+                    ]
+                    .spacing(10)
+                    .align_y(Alignment::Center),
                     Space::with_height(Length::FillPortion(1)),
-                    Element::map(self.palette_selector.view(), Message::FromPaletteSelector),
+                    row![
+                        Element::map(self.palette_selector.view(), Message::FromPaletteSelector),
+                        Element::map(self.color_editor.view(), Message::FromColorEditor),
+                    ]
+                    .spacing(10),
+                    Element::map(
+                        self.gradient
+                            .view(self.palette_selector.get_selected_color()),
+                        Message::FromGradient
+                    ),
+                    Element::map(
+                        self.palette_animations
+                            .view(self.palette_selector.get_selected_color()),
+                        Message::FromPaletteAnimations
+                    ),
                     Space::with_height(Length::FillPortion(1)),
                 ]
                 .align_x(Alignment::Center)
@@ -290,20 +1742,69 @@ impl App {
                         ))
                     ),
                     Space::with_height(Length::Fixed(10.)),
-                    column(self.graphics_files.iter().enumerate().map(|(index, file)| {
-                        button(file.path.file_name().unwrap().to_str().unwrap())
-                            .style(button::secondary)
-                            .on_press(Message::DisplayGraphicsFile(index))
+                    scrollable(
+                        column(self.graphics_files.iter().enumerate().map(|(index, file)| {
+                            let file_name = file.source.name();
+                            let file_name = if file.dirty {
+                                format!("{file_name} *")
+                            } else {
+                                file_name
+                            };
+                            row![
+                                button(text(match file.compression {
+                                    lz::Compression::None => file_name,
+                                    compression => format!("{file_name} ({compression})"),
+                                }))
+                                .style(button::secondary)
+                                .on_press(Message::DisplayGraphicsFile(index)),
+                                pick_list(BitDepth::ALL, Some(file.bit_depth), move |bit_depth| {
+                                    Message::SetGraphicsFileBitDepth(index, bit_depth)
+                                }),
+                                pick_list(
+                                    GraphicsSlot::ALL,
+                                    self.slot_of_file(index),
+                                    move |slot| { Message::SetGraphicsFileSlot(index, slot) }
+                                )
+                                .placeholder("No slot, can't paint"),
+                            ]
+                            .push_maybe(file.size_conflict.map(|size_on_disk| {
+                                text(format!("Now {size_on_disk:#X} bytes on disk"))
+                                    .style(text::danger)
+                            }))
+                            .spacing(10)
+                            .align_y(Alignment::Center)
                             .into()
-                    }))
-                    .spacing(10)
-                    .align_x(Alignment::Center),
-                    Space::with_height(Length::FillPortion(1)),
-                    if self.graphics_files.len() < 5 {
-                        container(button("Load more").on_press(Message::LoadMoreGraphicsFiles))
-                    } else {
-                        container(column![])
+                        }))
+                        .spacing(10)
+                        .align_x(Alignment::Center)
+                    )
+                    .height(Length::Fill),
+                    row![
+                        button("Open graphics…").on_press(Message::OpenGraphicsFiles),
+                        button("Open ROM…").on_press(Message::OpenRom),
+                        button("Save graphics").on_press_maybe(
+                            self.graphics_files
+                                .iter()
+                                .any(|file| file.dirty)
+                                .then_some(Message::SaveGraphicsFiles)
+                        ),
+                    ]
+                    .spacing(10),
+                    horizontal_rule(2),
+                    row![
+                        heading("Tile Editor"),
+                        checkbox("16x16", self.pixel_editor_edits_quad)
+                            .on_toggle(Message::SetPixelEditorEditsQuad),
+                    ]
+                    .align_y(Alignment::Center),
+                    match self.pixel_editor_target {
+                        Some(_) => container(Element::map(
+                            self.pixel_editor.view(),
+                            Message::FromPixelEditor
+                        )),
+                        None => container(text("Pick a tile in the graphics file to edit it")),
                     }
+                    .padding(10),
                 ]
                 .align_x(Alignment::Center)
                 .width(Length::FillPortion(1)),
@@ -311,87 +1812,9 @@ impl App {
             .spacing(10)
             .width(Length::Fill)
             .height(Length::Fill),
-        )
+        ])
         .width(Length::Fill)
         .height(Length::Fill)
         .into()
     }
 }
-
-async fn load_file(path: PathBuf) -> Option<(PathBuf, Arc<Vec<u8>>)> {
-    tokio::fs::read(&path)
-        .await
-        .ok()
-        .map(|contents| (path, Arc::new(contents)))
-}
-
-struct GraphicsFile {
-    path: PathBuf,
-    bytes: Arc<Vec<u8>>,
-    offset_in_all_bytes: usize,
-}
-impl GraphicsFile {
-    fn layout_all_tile_instances_from_file(
-        &self,
-        palette_line: usize,
-    ) -> Arc<Vec<tilemap::TileInstance>> {
-        let pal = palette_line as u8;
-        let mut tile_instances = vec![];
-
-        // Each iteration of the below for-loop is a 2x2 grid of 4 tiles which here we will call a
-        // quad.
-        let bits_per_pixel = 4;
-        let bits_per_tile = bits_per_pixel * 8 * 8;
-        let bytes_per_tile = bits_per_tile / 8; // it's 32
-        let bytes_per_quad = bytes_per_tile * 4;
-        let number_of_quads_in_this_file = self.bytes.len() / bytes_per_quad;
-
-        // If the all-bytes array was an all-tiles array, the following number would be the index
-        // of the first tile in this file.
-        let first_tile_id_of_file = (self.offset_in_all_bytes / bytes_per_tile) as u32;
-
-        let quads_per_row = 8;
-
-        for quad_index in 0..(number_of_quads_in_this_file) as u32 {
-            // These are in units of the visible pixels in the tile
-            let quad_left_x = quad_index % quads_per_row * 16;
-            let quad_top_y = quad_index / quads_per_row * 16;
-
-            let first_tile_id_of_quad = first_tile_id_of_file + quad_index * 4;
-
-            tile_instances.push(tilemap::TileInstance {
-                x: quad_left_x,
-                y: quad_top_y,
-                id: first_tile_id_of_quad,
-                pal,
-                scale: 1,
-                flags: 0,
-            });
-            tile_instances.push(tilemap::TileInstance {
-                x: quad_left_x + 8,
-                y: quad_top_y,
-                id: first_tile_id_of_quad + 1,
-                pal,
-                scale: 1,
-                flags: 0,
-            });
-            tile_instances.push(tilemap::TileInstance {
-                x: quad_left_x,
-                y: quad_top_y + 8,
-                id: first_tile_id_of_quad + 2,
-                pal,
-                scale: 1,
-                flags: 0,
-            });
-            tile_instances.push(tilemap::TileInstance {
-                x: quad_left_x + 8,
-                y: quad_top_y + 8,
-                id: first_tile_id_of_quad + 3,
-                pal,
-                scale: 1,
-                flags: 0,
-            });
-        }
-        Arc::new(tile_instances)
-    }
-}
@@ -1,3 +1,4 @@
+use std::ops::{Deref, Range};
 use std::sync::Arc;
 use std::sync::RwLock;
 
@@ -5,7 +6,7 @@ use glam::Vec2;
 
 use iced::widget::canvas;
 use iced::widget::canvas::Path;
-use iced::widget::stack;
+use iced::widget::canvas::Stroke;
 use iced::Color;
 use iced::Point;
 use iced::Renderer;
@@ -22,11 +23,207 @@ use iced::{
 use iced::widget::shader as shader_element;
 
 const PALETTE_ROWS: usize = 16;
+const COLORS_PER_ROW: usize = 16;
+
+/// The palette shared by every component that shows it, in the same way as the graphics bytes.
+/// Each change bumps the version, so that each component knows when to copy the palette to its GPU
+/// buffer again.
+#[derive(Debug)]
+pub struct Palette {
+    colors: Vec<Color>,
+    /// Colors shown in place of some of `colors`, by index, while palette animations play or a
+    /// gradient is previewed. They're only shown, so the colors read from the palette are never
+    /// these.
+    overriding_colors: Vec<(usize, Color)>,
+    output: ColorOutput,
+    version: u64,
+    /// Set when colors have been edited since the palette was read from its file.
+    dirty: bool,
+}
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: vec![Color::BLACK; crate::palette_file::COLOR_COUNT],
+            overriding_colors: vec![],
+            output: ColorOutput::default(),
+            version: 0,
+            dirty: false,
+        }
+    }
+}
+impl Palette {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Replaces every color with ones read from the palette's file. There are always 256, so extra
+    /// colors are left out and missing ones are black.
+    pub fn set_colors(&mut self, mut colors: Vec<Color>) {
+        colors.resize(crate::palette_file::COLOR_COUNT, Color::BLACK);
+        self.colors = colors;
+        self.version += 1;
+        self.dirty = false;
+    }
+
+    /// Whether the palette holds the given colors, as `set_colors` would set them.
+    pub fn has_colors(&self, colors: &[Color]) -> bool {
+        let mut colors = colors.to_vec();
+        colors.resize(crate::palette_file::COLOR_COUNT, Color::BLACK);
+        colors
+            .iter()
+            .zip(self.colors.iter())
+            .all(|(a, b)| a.into_rgba8() == b.into_rgba8())
+    }
+
+    /// Edits a color, which leaves the palette with unsaved edits.
+    pub fn set_color(&mut self, index: usize, color: Color) {
+        self.colors[index] = color;
+        self.version += 1;
+        self.dirty = true;
+    }
+
+    /// Shows the given colors in place of the palette's own until they're replaced, or cleared with
+    /// an empty list.
+    pub fn set_overriding_colors(&mut self, overriding_colors: Vec<(usize, Color)>) {
+        if overriding_colors != self.overriding_colors {
+            self.overriding_colors = overriding_colors;
+            self.version += 1;
+        }
+    }
+
+    pub fn get_output(&self) -> ColorOutput {
+        self.output
+    }
+
+    pub fn set_output(&mut self, output: ColorOutput) {
+        if output != self.output {
+            self.output = output;
+            self.version += 1;
+        }
+    }
+
+    /// The colors as they're shown, without overriding colors, for drawing and exporting on the
+    /// CPU.
+    pub fn displayed_colors(&self) -> Vec<Color> {
+        self.colors
+            .iter()
+            .map(|color| self.output.apply(*color))
+            .collect()
+    }
+
+    /// The colors as they're shown, as the linear RGBA samples that the shaders expect. They're
+    /// made from the same 8-bit colors as `displayed_colors`, so that the GPU's conversion back to
+    /// sRGB gives the same values the CPU does.
+    fn linear_samples(&self) -> Vec<f32> {
+        let mut colors = self.colors.clone();
+        for &(index, color) in self.overriding_colors.iter() {
+            colors[index] = color;
+        }
+        colors
+            .iter()
+            .flat_map(|color| {
+                let color = self.output.apply(*color);
+                [
+                    srgb_to_linear(color.r),
+                    srgb_to_linear(color.g),
+                    srgb_to_linear(color.b),
+                    color.a,
+                ]
+            })
+            .collect()
+    }
+}
+impl Deref for Palette {
+    type Target = [Color];
+
+    fn deref(&self) -> &[Color] {
+        &self.colors
+    }
+}
+
+/// How the palette's colors are turned into the colors that are shown and exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorOutput {
+    /// The colors as they are in the palette.
+    #[default]
+    AsIs,
+    /// The colors as a SNES outputs them: snapped to 5 bits per channel, then dimmed by the master
+    /// brightness set in INIDISP, from 0 to 15.
+    Snes(u8),
+}
+impl ColorOutput {
+    /// Gives the color that's shown for a palette color, rounded to 8 bits per channel.
+    pub fn apply(self, color: Color) -> Color {
+        let [r, g, b, a] = color.into_rgba8();
+        let [r, g, b] = match self {
+            Self::AsIs => [r, g, b],
+            // Brightness 0 blanks the screen.
+            Self::Snes(0) => [0, 0, 0],
+            Self::Snes(brightness) => {
+                // Other brightnesses scale linearly in the DAC, from 2/16 at 1 up to full at 15.
+                let scale = (brightness.min(15) as f32 + 1.) / 16.;
+                crate::bgr555::to_rgb8(crate::bgr555::from_rgb8([r, g, b]))
+                    .map(|channel| (channel as f32 * scale).round() as u8)
+            }
+        };
+        Color::from_rgba8(r, g, b, a as f32 / 255.)
+    }
+}
+
+/// The exact sRGB transfer function, rather than a 2.2 gamma curve.
+pub fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// What the tiles being edited are, which decides which palette lines they can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaletteContext {
+    /// 4bpp layer 1 and 2 tiles, which use lines 0-7.
+    #[default]
+    Layer12Bg,
+    /// 2bpp layer 3 tiles, which use the first 32 colors, so lines 0-1, as 8 groups of 4 colors.
+    Layer3,
+    /// 4bpp sprite tiles, which use lines 8-F.
+    Sprites,
+}
+impl PaletteContext {
+    pub const ALL: [PaletteContext; 3] = [Self::Layer12Bg, Self::Layer3, Self::Sprites];
+
+    pub fn selectable_lines(self) -> Range<usize> {
+        match self {
+            Self::Layer12Bg => 0..8,
+            Self::Layer3 => 0..2,
+            Self::Sprites => 8..16,
+        }
+    }
+}
+impl std::fmt::Display for PaletteContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Layer12Bg => "Layer 1/2 BG",
+            Self::Layer3 => "Layer 3",
+            Self::Sprites => "Sprites",
+        })
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum PublicMessage {
-    /// Raised when user presses then releases on the same palette line
-    PaletteLineClicked(usize),
+    /// Raised when user presses then releases on the same color, with its line and its index in the
+    /// line. Colors in lines the context doesn't allow can't be clicked.
+    PaletteColorClicked(usize, usize),
+    /// Raised when user right clicks a color, with its line and its index in the line.
+    PaletteColorRightClicked(usize, usize),
+    /// Raised when the cursor moves onto another color, or off the palette.
+    ColorHovered(Option<(usize, usize)>),
 }
 
 /// Parent of this component should pass this Envelope to the Component::update function, which may return a PublicMessage.
@@ -35,62 +232,170 @@ pub struct Envelope(PrivateMessage);
 
 #[derive(Debug, Clone, Copy)]
 enum PrivateMessage {
-    CursorMovedOverLine(usize),
+    /// Carries the line and the index in the line of the color under the cursor.
+    CursorMovedOverColor(usize, usize),
     LeftButtonPressedInside,
+    RightButtonPressedInside,
     LeftButtonReleasedInside,
     CursorExited,
 }
 
 pub struct Component {
-    pub selected_line: usize,
+    selected_line: usize,
+    /// Which group of 4 colors in the selected line 2bpp tiles use in the layer 3 context, 0-3.
+    selected_group: usize,
+    context: PaletteContext,
     palette_program: PaletteProgram,
     overlay: PaletteCanvasOverlay,
     line_hovered: Option<usize>,
-    line_mouse_pressed_on: Option<usize>,
+    /// The index in the hovered line of the color under the cursor.
+    index_hovered: Option<usize>,
+    /// The line and index in the line of the color the left button was pressed on.
+    color_mouse_pressed_on: Option<(usize, usize)>,
 }
 impl Component {
-    pub fn new() -> Self {
+    pub fn new(palette: Arc<RwLock<Palette>>) -> Self {
+        let selected_line = 3;
         Self {
-            selected_line: 3,
-            palette_program: PaletteProgram::new(),
-            overlay: PaletteCanvasOverlay::new(),
+            selected_line,
+            selected_group: 0,
+            context: PaletteContext::default(),
+            palette_program: PaletteProgram::new(palette),
+            overlay: PaletteCanvasOverlay::new(selected_line),
             line_hovered: None,
-            line_mouse_pressed_on: None,
+            index_hovered: None,
+            color_mouse_pressed_on: None,
+        }
+    }
+
+    pub fn get_selected_line(&self) -> usize {
+        self.selected_line
+    }
+
+    pub fn set_selected_line(&mut self, line: usize) {
+        self.selected_line = line;
+        self.overlay.selected_line = line;
+        self.overlay.canvas_cache.clear();
+    }
+
+    pub fn get_selected_group(&self) -> usize {
+        self.selected_group
+    }
+
+    pub fn set_selected_group(&mut self, group: usize) {
+        self.selected_group = group;
+        self.set_overlay_selected_group();
+    }
+
+    /// The index in the palette of the first of the colors tiles are shown in: the selected
+    /// line's, or in the layer 3 context, the selected group of 4 colors'.
+    pub fn get_selected_first_color(&self) -> usize {
+        self.selected_line * COLORS_PER_ROW
+            + match self.context {
+                PaletteContext::Layer3 => self.selected_group * 4,
+                PaletteContext::Layer12Bg | PaletteContext::Sprites => 0,
+            }
+    }
+
+    fn set_overlay_selected_group(&mut self) {
+        self.overlay.selected_group =
+            (self.context == PaletteContext::Layer3).then_some(self.selected_group);
+        self.overlay.canvas_cache.clear();
+    }
+
+    pub fn get_context(&self) -> PaletteContext {
+        self.context
+    }
+
+    /// Changes which lines can be selected, moving the selection to the first of them if the
+    /// selected line no longer can be.
+    pub fn set_context(&mut self, context: PaletteContext) {
+        self.context = context;
+        self.overlay.selectable_lines = context.selectable_lines();
+        if !context.selectable_lines().contains(&self.selected_line) {
+            self.set_selected_line(context.selectable_lines().start);
+        }
+        self.set_overlay_selected_group();
+    }
+
+    /// The index in the palette of the color highlighted as selected, if any.
+    pub fn get_selected_color(&self) -> Option<usize> {
+        self.overlay.selected_color
+    }
+
+    /// Highlights the colors being drawn with, by their indices in the palette. Clicking a color
+    /// selects it, and right clicking makes it the secondary color, but the parent can override
+    /// either, such as when the color can't be drawn with.
+    pub fn set_selected_colors(
+        &mut self,
+        selected_color: Option<usize>,
+        secondary_color: Option<usize>,
+    ) {
+        if (self.overlay.selected_color, self.overlay.secondary_color)
+            != (selected_color, secondary_color)
+        {
+            self.overlay.selected_color = selected_color;
+            self.overlay.secondary_color = secondary_color;
+            self.overlay.canvas_cache.clear();
+        }
+    }
+
+    fn get_color_hovered(&self) -> Option<(usize, usize)> {
+        self.line_hovered.zip(self.index_hovered)
+    }
+
+    fn set_line_hovered(&mut self, line_hovered: Option<usize>) {
+        if self.line_hovered != line_hovered {
+            self.line_hovered = line_hovered;
+            self.overlay.line_hovered = line_hovered;
+            self.overlay.canvas_cache.clear();
         }
     }
 
     pub fn update(&mut self, envelope: Envelope) -> Option<PublicMessage> {
         match envelope.0 {
-            PrivateMessage::CursorMovedOverLine(line) => {
-                self.line_hovered = Some(line);
-                None
+            PrivateMessage::CursorMovedOverColor(line, index) => {
+                let color_hovered_before = self.get_color_hovered();
+                self.set_line_hovered(Some(line));
+                self.index_hovered = Some(index);
+                (color_hovered_before != Some((line, index)))
+                    .then_some(PublicMessage::ColorHovered(Some((line, index))))
             }
             PrivateMessage::LeftButtonPressedInside => {
-                self.line_mouse_pressed_on = self.line_hovered;
+                self.color_mouse_pressed_on = self.get_color_hovered();
                 None
             }
             PrivateMessage::LeftButtonReleasedInside => {
-                if let (Some(line_mouse_pressed_on), Some(line_hovered)) =
-                    (self.line_mouse_pressed_on, self.line_hovered)
+                let (line, index) = self.color_mouse_pressed_on.take()?;
+                if self.get_color_hovered() != Some((line, index))
+                    || !self.context.selectable_lines().contains(&line)
                 {
-                    if line_mouse_pressed_on == line_hovered {
-                        Some(PublicMessage::PaletteLineClicked(line_hovered))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
+                    return None;
                 }
+                self.set_selected_colors(Some(line * 16 + index), self.overlay.secondary_color);
+                Some(PublicMessage::PaletteColorClicked(line, index))
+            }
+            PrivateMessage::RightButtonPressedInside => {
+                let (line, index) = self.get_color_hovered()?;
+                if !self.context.selectable_lines().contains(&line) {
+                    return None;
+                }
+                self.set_selected_colors(self.overlay.selected_color, Some(line * 16 + index));
+                Some(PublicMessage::PaletteColorRightClicked(line, index))
             }
             PrivateMessage::CursorExited => {
-                self.line_hovered = None;
-                self.line_mouse_pressed_on = None;
-                None
+                let color_hovered_before = self.get_color_hovered();
+                self.set_line_hovered(None);
+                self.index_hovered = None;
+                self.color_mouse_pressed_on = None;
+                color_hovered_before
+                    .is_some()
+                    .then_some(PublicMessage::ColorHovered(None))
             }
         }
     }
 
-    pub fn view(&self) -> Element<Envelope> {
+    pub fn view(&self) -> Element<'_, Envelope> {
         use iced::widget::*;
 
         let dim = 256;
@@ -100,11 +405,13 @@ impl Component {
         ))
         .on_press(Envelope(PrivateMessage::LeftButtonPressedInside))
         .on_release(Envelope(PrivateMessage::LeftButtonReleasedInside))
+        .on_right_press(Envelope(PrivateMessage::RightButtonPressedInside))
         .on_exit(Envelope(PrivateMessage::CursorExited))
         .on_move(move |point| {
             println!("point: {point:?}");
-            Envelope(PrivateMessage::CursorMovedOverLine(
-                ((point.y / dim as f32) * PALETTE_ROWS as f32) as _,
+            Envelope(PrivateMessage::CursorMovedOverColor(
+                (((point.y / dim as f32) * PALETTE_ROWS as f32) as usize).min(PALETTE_ROWS - 1),
+                (((point.x / dim as f32) * COLORS_PER_ROW as f32) as usize).min(COLORS_PER_ROW - 1),
             ))
         })
         .into()
@@ -115,11 +422,13 @@ type LazyPipelineArc = Arc<RwLock<Option<PaletteShaderPipeline>>>;
 
 struct PaletteProgram {
     pipeline: LazyPipelineArc,
+    palette: Arc<RwLock<Palette>>,
 }
 impl PaletteProgram {
-    fn new() -> Self {
+    fn new(palette: Arc<RwLock<Palette>>) -> Self {
         Self {
             pipeline: Default::default(),
+            palette,
         }
     }
 }
@@ -146,6 +455,7 @@ impl shader::Program<Envelope> for PaletteProgram {
     ) -> Self::Primitive {
         PaletteFrameInfo {
             pipeline: self.pipeline.clone(),
+            palette: self.palette.clone(),
         }
     }
 }
@@ -161,6 +471,7 @@ pub struct Uniforms {
 #[derive(Debug)]
 pub struct PaletteFrameInfo {
     pipeline: LazyPipelineArc,
+    palette: Arc<RwLock<Palette>>,
 }
 impl shader::Primitive for PaletteFrameInfo {
     fn prepare(
@@ -184,7 +495,9 @@ impl shader::Primitive for PaletteFrameInfo {
         let pipeline = storage.get_mut::<PaletteShaderPipeline>().unwrap();
         */
         let mut pipeline = self.pipeline.write().unwrap();
-        let pipeline = pipeline.get_or_insert_with(|| PaletteShaderPipeline::new(device, format));
+        let pipeline = pipeline
+            .get_or_insert_with(|| PaletteShaderPipeline::new(device, format, &self.palette));
+        pipeline.write_palette_buffer_if_needed(queue, &self.palette);
         pipeline.write_uniforms(
             queue,
             &Uniforms {
@@ -214,11 +527,14 @@ impl shader::Primitive for PaletteFrameInfo {
 #[derive(Debug)]
 struct PaletteShaderPipeline {
     pipeline: wgpu::RenderPipeline,
+    palette_buffer: wgpu::Buffer,
+    /// The version of the palette that the palette buffer holds.
+    palette_version: u64,
     bind_group: wgpu::BindGroup,
 }
 
 impl PaletteShaderPipeline {
-    fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+    fn new(device: &wgpu::Device, format: wgpu::TextureFormat, palette: &RwLock<Palette>) -> Self {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("palette shader module"),
             source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
@@ -252,17 +568,8 @@ impl PaletteShaderPipeline {
             multiview: None,
         });
 
-        let mut palette_image = image::open("assets/palette.png").unwrap().to_rgba32f();
-        palette_image
-            .as_flat_samples_mut()
-            .samples
-            .iter_mut()
-            .for_each(|c| *c = c.powf(2.2));
-        let palette_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("palette palette buffer"),
-            contents: bytemuck::cast_slice(palette_image.as_flat_samples().samples),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
+        let palette = palette.read().unwrap();
+        let palette_buffer = create_palette_buffer(device, "palette palette buffer", &palette);
         let bind_group_layout = pipeline.get_bind_group_layout(0);
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("palette bind group"),
@@ -275,10 +582,21 @@ impl PaletteShaderPipeline {
 
         Self {
             pipeline,
+            palette_buffer,
+            palette_version: palette.version(),
             bind_group,
         }
     }
 
+    fn write_palette_buffer_if_needed(&mut self, queue: &wgpu::Queue, palette: &RwLock<Palette>) {
+        self.palette_version = write_palette_buffer_if_changed(
+            queue,
+            &self.palette_buffer,
+            palette,
+            self.palette_version,
+        );
+    }
+
     fn write_uniforms(&mut self, _queue: &wgpu::Queue, _uniforms: &Uniforms) {}
 
     fn render(
@@ -319,11 +637,28 @@ impl PaletteShaderPipeline {
 
 struct PaletteCanvasOverlay {
     pub canvas_cache: canvas::Cache,
+    pub line_hovered: Option<usize>,
+    pub selected_line: usize,
+    /// The group of 4 colors in the selected line that's outlined instead of the whole line, if
+    /// any.
+    pub selected_group: Option<usize>,
+    /// Lines outside of these are hatched out.
+    pub selectable_lines: Range<usize>,
+    /// Index in the palette of the color drawn with the left mouse button.
+    pub selected_color: Option<usize>,
+    /// Index in the palette of the color drawn with the right mouse button.
+    pub secondary_color: Option<usize>,
 }
 impl PaletteCanvasOverlay {
-    pub fn new() -> Self {
+    pub fn new(selected_line: usize) -> Self {
         Self {
             canvas_cache: canvas::Cache::default(),
+            line_hovered: None,
+            selected_line,
+            selected_group: None,
+            selectable_lines: PaletteContext::default().selectable_lines(),
+            selected_color: None,
+            secondary_color: None,
         }
     }
 
@@ -374,17 +709,168 @@ impl<Message> canvas::Program<Message> for PaletteCanvasOverlay {
         _cursor: iced::mouse::Cursor,
     ) -> Vec<canvas::Geometry<Renderer>> {
         vec![self.canvas_cache.draw(renderer, bounds.size(), |frame| {
-            frame.fill(
-                // Subtract 2 in order to get the hatched paths to more accurately position
-                // themselves over the pixels they're supposed to be covering, since the canvas can
-                // shift relative to the shader element depending on final calculated layout
-                // position.
-                &Self::get_hatched_path(
-                    Point::new(-2., bounds.height / 2. - 2.),
-                    Size::new(bounds.width + 2., bounds.height / 2. + 2.),
-                ),
-                Color::new(0.1, 0.1, 0.1, 1.0),
+            let cell_size = Size::new(
+                bounds.width / COLORS_PER_ROW as f32,
+                bounds.height / PALETTE_ROWS as f32,
             );
+
+            for hatched_lines in [
+                0..self.selectable_lines.start,
+                self.selectable_lines.end..PALETTE_ROWS,
+            ] {
+                if hatched_lines.is_empty() {
+                    continue;
+                }
+                frame.fill(
+                    // Subtract 2 in order to get the hatched paths to more accurately position
+                    // themselves over the pixels they're supposed to be covering, since the canvas
+                    // can shift relative to the shader element depending on final calculated
+                    // layout position.
+                    &Self::get_hatched_path(
+                        Point::new(-2., hatched_lines.start as f32 * cell_size.height - 2.),
+                        Size::new(
+                            bounds.width + 2.,
+                            hatched_lines.len() as f32 * cell_size.height + 2.,
+                        ),
+                    ),
+                    Color::new(0.1, 0.1, 0.1, 1.0),
+                );
+            }
+
+            // The selected line is drawn last, so that it's on top when it's also hovered. Only the
+            // selected group of colors in it is outlined, if there is one.
+            let selected_colors = match self.selected_group {
+                Some(group) => group * 4..group * 4 + 4,
+                None => 0..COLORS_PER_ROW,
+            };
+            for (line, colors, outline_color) in [
+                (
+                    self.line_hovered,
+                    0..COLORS_PER_ROW,
+                    Color::new(0.5, 0.5, 0.5, 1.),
+                ),
+                (
+                    Some(self.selected_line),
+                    selected_colors,
+                    Color::new(0.9, 0.9, 0.9, 1.),
+                ),
+            ] {
+                let Some(line) = line else {
+                    continue;
+                };
+                let stroke_width = 2.;
+                frame.stroke_rectangle(
+                    Point::new(
+                        colors.start as f32 * cell_size.width + stroke_width / 2.,
+                        line as f32 * cell_size.height + stroke_width / 2.,
+                    ),
+                    Size::new(
+                        colors.len() as f32 * cell_size.width - stroke_width,
+                        cell_size.height - stroke_width,
+                    ),
+                    Stroke {
+                        width: stroke_width,
+                        style: outline_color.into(),
+                        ..Default::default()
+                    },
+                );
+            }
+
+            // The selected color is drawn last, so that it's on top when both are the same color.
+            for (color, outline_color) in [
+                (self.secondary_color, Color::new(0.5, 0.5, 0.5, 1.)),
+                (self.selected_color, Color::new(0.9, 0.9, 0.9, 1.)),
+            ] {
+                let Some(color) = color else {
+                    continue;
+                };
+                let stroke_width = 2.;
+                // Inset by more than the line outlines are, so that the two don't run together.
+                let inset = stroke_width * 2.;
+                frame.stroke_rectangle(
+                    Point::new(
+                        (color % COLORS_PER_ROW) as f32 * cell_size.width + inset,
+                        (color / COLORS_PER_ROW) as f32 * cell_size.height + inset,
+                    ),
+                    Size::new(cell_size.width - inset * 2., cell_size.height - inset * 2.),
+                    Stroke {
+                        width: stroke_width,
+                        style: outline_color.into(),
+                        ..Default::default()
+                    },
+                );
+            }
         })]
     }
 }
+
+/// Creates a buffer holding the palette's colors as linear RGBA samples. Every palette is the same
+/// size, so the buffer never needs recreating.
+pub fn create_palette_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    palette: &Palette,
+) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::cast_slice(&palette.linear_samples()),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// Copies the palette into a palette buffer, if it's changed since the given version, and returns
+/// the version the buffer now holds.
+pub fn write_palette_buffer_if_changed(
+    queue: &wgpu::Queue,
+    palette_buffer: &wgpu::Buffer,
+    palette_rw: &RwLock<Palette>,
+    written_version: u64,
+) -> u64 {
+    let palette = palette_rw.read().unwrap();
+    if palette.version() != written_version {
+        queue.write_buffer(
+            palette_buffer,
+            0,
+            bytemuck::cast_slice(&palette.linear_samples()),
+        );
+    }
+    palette.version()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_make_the_palette_dirty_until_it_is_read_again() {
+        let mut palette = Palette::default();
+        assert!(!palette.is_dirty());
+        palette.set_color(1, Color::WHITE);
+        assert!(palette.is_dirty());
+        assert!(palette.has_colors(&[Color::BLACK, Color::WHITE]));
+        assert!(!palette.has_colors(&[]));
+        palette.set_colors(vec![]);
+        assert!(!palette.is_dirty());
+    }
+
+    #[test]
+    fn snes_output_snaps_to_5_bits_then_scales_by_brightness() {
+        let snes = |brightness, [r, g, b]: [u8; 3]| {
+            let [r, g, b, _] = ColorOutput::Snes(brightness)
+                .apply(Color::from_rgb8(r, g, b))
+                .into_rgba8();
+            [r, g, b]
+        };
+        assert_eq!(snes(15, [255, 128, 7]), [255, 132, 0]);
+        assert_eq!(snes(7, [255, 255, 255]), [128, 128, 128]);
+        assert_eq!(snes(1, [255, 255, 255]), [32, 32, 32]);
+        assert_eq!(snes(0, [255, 255, 255]), [0, 0, 0]);
+        assert_eq!(snes(0, [40, 0, 0]), [0, 0, 0]);
+        // Brightnesses past 15 are treated as 15.
+        assert_eq!(snes(200, [255, 255, 255]), [255, 255, 255]);
+        assert_eq!(
+            ColorOutput::AsIs.apply(Color::from_rgb8(255, 128, 7)),
+            Color::from_rgb8(255, 128, 7)
+        );
+    }
+}
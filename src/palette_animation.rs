@@ -0,0 +1,345 @@
+//! Palette animations, where a color steps through a sequence of colors, each shown for a number of
+//! frames. SMW animates colors such as the flashing yellow of question blocks this way, and hacks
+//! add their own.
+
+use std::sync::{Arc, RwLock};
+
+use iced::{
+    time::{self, Duration, Instant},
+    Alignment, Element, Subscription,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{bgr555, palette::Palette};
+
+/// How often the SNES draws a frame, on NTSC consoles.
+pub const FRAMES_PER_SECOND: f64 = 60.0988;
+
+/// These are messages that parent is expected to want to handle.
+#[derive(Debug, Clone, Copy)]
+pub enum PublicMessage {
+    /// Raised when the colors from `get_overriding_colors` change, so that the parent can show
+    /// them in the palette.
+    OverridingColorsChanged,
+}
+
+/// Parent of this component should pass this Envelope to the Component::update function, which may return a PublicMessage.
+#[derive(Debug, Clone)]
+pub struct Envelope(PrivateMessage);
+
+#[derive(Debug, Clone)]
+enum PrivateMessage {
+    PlayToggled(bool),
+    AnimateColorPressed(usize),
+    RemovePressed(usize),
+    StepsInput(usize, String),
+    StepsSubmitted(usize),
+    Tick(Instant),
+}
+
+pub struct Component {
+    palette: Arc<RwLock<Palette>>,
+    animations: Vec<PaletteAnimation>,
+    /// The text of each animation's steps, as it's being typed.
+    inputs: Vec<String>,
+    /// When the animations started playing, if they're playing.
+    started: Option<Instant>,
+    /// The frame shown, counting from when the animations started playing.
+    frame: u64,
+}
+impl Component {
+    pub fn new(palette: Arc<RwLock<Palette>>) -> Self {
+        Self {
+            palette,
+            animations: vec![],
+            inputs: vec![],
+            started: None,
+            frame: 0,
+        }
+    }
+
+    pub fn get_animations(&self) -> &[PaletteAnimation] {
+        &self.animations
+    }
+
+    pub fn set_animations(&mut self, animations: Vec<PaletteAnimation>) {
+        self.inputs = animations
+            .iter()
+            .map(|animation| format_steps(&animation.steps))
+            .collect();
+        self.animations = animations;
+    }
+
+    /// Each animation's color on the frame shown, by palette index, in BGR555. There are none
+    /// while the animations aren't playing.
+    pub fn get_overriding_colors(&self) -> Vec<(usize, u16)> {
+        if self.started.is_none() {
+            return vec![];
+        }
+        self.animations
+            .iter()
+            .filter_map(|animation| {
+                Some((animation.color_index, animation.color_at_frame(self.frame)?))
+            })
+            .collect()
+    }
+
+    pub fn update(&mut self, envelope: Envelope) -> Option<PublicMessage> {
+        match envelope.0 {
+            PrivateMessage::PlayToggled(playing) => {
+                self.started = playing.then(Instant::now);
+                self.frame = 0;
+            }
+            PrivateMessage::AnimateColorPressed(color_index) => {
+                let [r, g, b, _] = self.palette.read().unwrap()[color_index].into_rgba8();
+                let steps = vec![
+                    AnimationStep {
+                        color: bgr555::from_rgb8([r, g, b]),
+                        frames: 8,
+                    },
+                    AnimationStep {
+                        color: 0x7FFF,
+                        frames: 8,
+                    },
+                ];
+                self.inputs.push(format_steps(&steps));
+                self.animations
+                    .push(PaletteAnimation { color_index, steps });
+            }
+            PrivateMessage::RemovePressed(animation_index) => {
+                self.animations.remove(animation_index);
+                self.inputs.remove(animation_index);
+            }
+            PrivateMessage::StepsInput(animation_index, input) => {
+                self.inputs[animation_index] = input;
+                return None;
+            }
+            PrivateMessage::StepsSubmitted(animation_index) => {
+                let animation = &mut self.animations[animation_index];
+                match parse_steps(&self.inputs[animation_index]) {
+                    Ok(steps) => animation.steps = steps,
+                    Err(error) => println!("{error}"),
+                }
+                self.inputs[animation_index] = format_steps(&animation.steps);
+            }
+            PrivateMessage::Tick(now) => {
+                let started = self.started?;
+                self.frame = frame_at(now.saturating_duration_since(started));
+            }
+        }
+        Some(PublicMessage::OverridingColorsChanged)
+    }
+
+    /// Steps the animations every frame while they're playing.
+    pub fn subscription(&self) -> Subscription<Envelope> {
+        if self.started.is_some() && !self.animations.is_empty() {
+            time::every(Duration::from_secs_f64(1. / FRAMES_PER_SECOND))
+                .map(|now| Envelope(PrivateMessage::Tick(now)))
+        } else {
+            Subscription::none()
+        }
+    }
+
+    /// Offers to animate the selected color, if it isn't already.
+    pub fn view(&self, selected_color: Option<usize>) -> Element<'_, Envelope> {
+        use iced::widget::{column, row, *};
+
+        column![row![
+            text("Palette animations"),
+            checkbox("Play", self.started.is_some())
+                .on_toggle(|playing| Envelope(PrivateMessage::PlayToggled(playing))),
+            button("Animate color").on_press_maybe(
+                selected_color
+                    .filter(|color_index| {
+                        !self
+                            .animations
+                            .iter()
+                            .any(|animation| animation.color_index == *color_index)
+                    })
+                    .map(|color_index| Envelope(PrivateMessage::AnimateColorPressed(color_index)))
+            ),
+        ]
+        .spacing(10)
+        .align_y(Alignment::Center)]
+        .extend(
+            self.animations
+                .iter()
+                .zip(self.inputs.iter())
+                .enumerate()
+                .map(|(animation_index, (animation, input))| {
+                    row![
+                        text(format!(
+                            "Color {:X}:{:X}",
+                            animation.color_index / 16,
+                            animation.color_index % 16
+                        )),
+                        text_input("$7FFF:8 $03FF:8", input)
+                            .on_input(move |input| {
+                                Envelope(PrivateMessage::StepsInput(animation_index, input))
+                            })
+                            .on_submit(Envelope(PrivateMessage::StepsSubmitted(animation_index)))
+                            .width(300),
+                        button("Remove")
+                            .style(button::secondary)
+                            .on_press(Envelope(PrivateMessage::RemovePressed(animation_index))),
+                    ]
+                    .spacing(10)
+                    .align_y(Alignment::Center)
+                    .into()
+                }),
+        )
+        .spacing(5)
+        .padding(10)
+        .into()
+    }
+}
+
+/// The frame being drawn after the given time, counting from 0.
+pub fn frame_at(elapsed: Duration) -> u64 {
+    (elapsed.as_secs_f64() * FRAMES_PER_SECOND) as u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnimationStep {
+    /// The color shown during the step, in BGR555.
+    pub color: u16,
+    /// How many frames the step lasts.
+    pub frames: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaletteAnimation {
+    /// The index in the palette of the animated color.
+    pub color_index: usize,
+    /// The steps, which repeat forever.
+    pub steps: Vec<AnimationStep>,
+}
+impl PaletteAnimation {
+    /// The color shown on the given frame, counting from the start of the first step, or None if
+    /// no step lasts any frames.
+    pub fn color_at_frame(&self, frame: u64) -> Option<u16> {
+        let total_frames = self
+            .steps
+            .iter()
+            .map(|step| step.frames as u64)
+            .sum::<u64>();
+        if total_frames == 0 {
+            return None;
+        }
+        let mut frame = frame % total_frames;
+        for step in self.steps.iter() {
+            if frame < step.frames as u64 {
+                return Some(step.color);
+            }
+            frame -= step.frames as u64;
+        }
+        None
+    }
+}
+
+/// Reads steps written as SNES colors and frame counts, such as `$7FFF:8 $03FF:4`.
+pub fn parse_steps(text: &str) -> Result<Vec<AnimationStep>, String> {
+    text.split_whitespace()
+        .map(|step| {
+            let (color, frames) = step.split_once(':').ok_or_else(|| {
+                format!("{step:?} should be a color and a frame count, like $7FFF:8")
+            })?;
+            let color = u16::from_str_radix(color.trim_start_matches('$'), 16)
+                .ok()
+                .filter(|color| *color <= 0x7FFF)
+                .ok_or_else(|| format!("{color:?} isn't a SNES color"))?;
+            let frames = frames
+                .parse()
+                .map_err(|_| format!("{frames:?} isn't a number of frames"))?;
+            Ok(AnimationStep { color, frames })
+        })
+        .collect()
+}
+
+/// Writes steps in the form `parse_steps` reads.
+pub fn format_steps(steps: &[AnimationStep]) -> String {
+    steps
+        .iter()
+        .map(|step| format!("${:04X}:{}", step.color, step.frames))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn question_block() -> PaletteAnimation {
+        PaletteAnimation {
+            color_index: 0x64,
+            steps: vec![
+                AnimationStep {
+                    color: 0x03FF,
+                    frames: 8,
+                },
+                AnimationStep {
+                    color: 0x7FFF,
+                    frames: 4,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn parses_steps() {
+        assert_eq!(
+            parse_steps(" $03FF:8   7fff:4 "),
+            Ok(question_block().steps)
+        );
+        assert_eq!(parse_steps(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn rejects_bad_steps() {
+        assert!(parse_steps("$03FF").is_err());
+        assert!(parse_steps("$8000:8").is_err());
+        assert!(parse_steps("$GGGG:8").is_err());
+        assert!(parse_steps("$03FF:-1").is_err());
+        assert!(parse_steps("$03FF:8 $7FFF:").is_err());
+    }
+
+    #[test]
+    fn formats_steps_the_way_they_are_parsed() {
+        let steps = question_block().steps;
+        assert_eq!(format_steps(&steps), "$03FF:8 $7FFF:4");
+        assert_eq!(parse_steps(&format_steps(&steps)), Ok(steps));
+    }
+
+    #[test]
+    fn shows_each_step_for_its_frames_then_repeats() {
+        let animation = question_block();
+        let colors = (0..26)
+            .map(|frame| animation.color_at_frame(frame).unwrap())
+            .collect::<Vec<_>>();
+        let mut expected = [vec![0x03FF; 8], vec![0x7FFF; 4]].concat();
+        expected = [expected.clone(), expected.clone(), expected[..2].to_vec()].concat();
+        assert_eq!(colors, expected);
+    }
+
+    #[test]
+    fn steps_without_frames_show_nothing() {
+        let mut animation = question_block();
+        animation.steps[1].frames = 0;
+        assert_eq!(animation.color_at_frame(9), Some(0x03FF));
+        animation.steps[0].frames = 0;
+        assert_eq!(animation.color_at_frame(0), None);
+        animation.steps.clear();
+        assert_eq!(animation.color_at_frame(0), None);
+    }
+
+    #[test]
+    fn counts_frames_at_the_snes_frame_rate() {
+        assert_eq!(frame_at(Duration::ZERO), 0);
+        assert_eq!(frame_at(Duration::from_secs_f64(0.0166)), 0);
+        assert_eq!(frame_at(Duration::from_secs_f64(0.0167)), 1);
+        assert_eq!(frame_at(Duration::from_secs(1)), 60);
+        // The extra 0.0988 frames a second add up to a frame within about 10 seconds.
+        assert_eq!(frame_at(Duration::from_secs(10)), 600);
+        assert_eq!(frame_at(Duration::from_secs(11)), 661);
+    }
+}
@@ -0,0 +1,309 @@
+//! Reading palettes from the files other SNES tools save them as, and writing them back out.
+
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use iced::Color;
+
+use crate::bgr555;
+
+/// The number of colors in CGRAM, and so in every palette the editor shows.
+pub const COLOR_COUNT: usize = 256;
+
+/// Where the palette is read from when a project doesn't say, relative to the working directory.
+pub const DEFAULT_PALETTE_PATH: &str = "assets/palette.png";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteFormat {
+    /// Lunar Magic's palette files: all 256 colors in BGR555, then the back area color.
+    Mw3,
+    /// 256 colors as 8-bit RGB, as saved by YY-CHR and most emulators.
+    Pal,
+    /// A dump of CGRAM, or any part of it from the start, in BGR555.
+    Bgr555,
+    /// An image whose pixels, left to right and top to bottom, are the colors.
+    Png,
+}
+impl PaletteFormat {
+    const MW3_LEN: usize = COLOR_COUNT * 2 + 2;
+    const PAL_LEN: usize = COLOR_COUNT * 3;
+
+    /// Works out a file's format from its extension, or from its size if the extension isn't one
+    /// of the formats'.
+    fn guess(path: &Path, len: usize) -> Option<Self> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("mw3") => Some(Self::Mw3),
+            // Some tools save BGR555 palettes as .pal too, so only full RGB palettes and sizes that
+            // can't be BGR555 are taken as RGB.
+            Some("pal") if len == Self::PAL_LEN || !len.is_multiple_of(2) => Some(Self::Pal),
+            Some("png") => Some(Self::Png),
+            _ => match len {
+                Self::MW3_LEN => Some(Self::Mw3),
+                Self::PAL_LEN => Some(Self::Pal),
+                len if len.is_multiple_of(2) && len <= COLOR_COUNT * 2 => Some(Self::Bgr555),
+                _ => None,
+            },
+        }
+    }
+}
+impl std::fmt::Display for PaletteFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Mw3 => "MW3",
+            Self::Pal => "PAL",
+            Self::Bgr555 => "BGR555",
+            Self::Png => "PNG",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LoadError {
+    Read(PathBuf, String),
+    UnknownFormat(PathBuf, usize),
+    WrongSize(PathBuf, PaletteFormat, usize),
+    Decode(PathBuf, String),
+}
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read(path, error) => write!(f, "Couldn't read palette {path:?}: {error}"),
+            Self::UnknownFormat(path, len) => write!(
+                f,
+                "Couldn't tell what kind of palette {path:?} is from its extension or its size \
+                 ({len:#X} bytes)"
+            ),
+            Self::WrongSize(path, format, len) => write!(
+                f,
+                "{path:?} is {len:#X} bytes, which is the wrong size for a {format} palette"
+            ),
+            Self::Decode(path, error) => write!(f, "Couldn't decode palette {path:?}: {error}"),
+        }
+    }
+}
+
+pub async fn pick_palette_file() -> Option<PathBuf> {
+    rfd::AsyncFileDialog::new()
+        .set_title("Open palette")
+        .add_filter("Palette files", &["mw3", "pal", "bin", "png"])
+        .add_filter("All files", &["*"])
+        .pick_file()
+        .await
+        .map(|file_handle| file_handle.path().to_path_buf())
+}
+
+/// Reads a palette in any of the formats. Palettes with fewer than 256 colors are filled out with
+/// black, and colors past the 256th are left out.
+pub fn load(path: &Path) -> Result<Vec<Color>, LoadError> {
+    let contents =
+        std::fs::read(path).map_err(|error| LoadError::Read(path.into(), error.to_string()))?;
+    let format = PaletteFormat::guess(path, contents.len())
+        .ok_or_else(|| LoadError::UnknownFormat(path.into(), contents.len()))?;
+    let mut colors = match format {
+        PaletteFormat::Mw3 => {
+            if contents.len() != PaletteFormat::MW3_LEN {
+                return Err(LoadError::WrongSize(path.into(), format, contents.len()));
+            }
+            let mut colors = bgr555_colors(&contents[..COLOR_COUNT * 2]);
+            // The back area color is what shows through color 0.
+            colors[0] = bgr555_colors(&contents[COLOR_COUNT * 2..])[0];
+            colors
+        }
+        PaletteFormat::Pal => contents
+            .chunks_exact(3)
+            .map(|rgb| Color::from_rgb8(rgb[0], rgb[1], rgb[2]))
+            .collect(),
+        PaletteFormat::Bgr555 => bgr555_colors(&contents),
+        PaletteFormat::Png => {
+            image::load_from_memory_with_format(&contents, image::ImageFormat::Png)
+                .map_err(|error| LoadError::Decode(path.into(), error.to_string()))?
+                .to_rgba8()
+                .pixels()
+                .map(|pixel| {
+                    let [r, g, b, a] = pixel.0;
+                    Color::from_rgba8(r, g, b, a as f32 / 255.)
+                })
+                .collect()
+        }
+    };
+    colors.resize(COLOR_COUNT, Color::BLACK);
+    Ok(colors)
+}
+
+fn bgr555_colors(bytes: &[u8]) -> Vec<Color> {
+    bytes
+        .chunks_exact(2)
+        .map(|color| bgr555::to_color(u16::from_le_bytes([color[0], color[1]])))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Mw3,
+    Pal,
+    /// An image 16 colors wide with one row per palette line, like `assets/palette.png`.
+    Png,
+    /// An asar table of `dw` directives, one per palette line, for patches to include.
+    Asm,
+}
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [Self::Mw3, Self::Pal, Self::Png, Self::Asm];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Mw3 => "mw3",
+            Self::Pal => "pal",
+            Self::Png => "png",
+            Self::Asm => "asm",
+        }
+    }
+}
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Mw3 => "Lunar Magic .mw3",
+            Self::Pal => "RGB .pal",
+            Self::Png => "PNG swatch",
+            Self::Asm => "asar dw table",
+        })
+    }
+}
+
+/// Which palette lines are exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportedLines {
+    #[default]
+    All,
+    Selected,
+    /// The lines the palette context allows.
+    Context,
+}
+impl ExportedLines {
+    pub const ALL: [ExportedLines; 3] = [Self::All, Self::Selected, Self::Context];
+}
+impl std::fmt::Display for ExportedLines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::All => "All lines",
+            Self::Selected => "Selected line",
+            Self::Context => "Context's lines",
+        })
+    }
+}
+
+/// Writes the given palette lines of `colors` in a format. .mw3 and .pal files always hold all 256
+/// colors, since the tools that read them expect that, so the other lines are left black.
+pub fn encode(
+    colors: &[Color],
+    lines: Range<usize>,
+    format: ExportFormat,
+) -> Result<Vec<u8>, image::ImageError> {
+    let exported_colors = || {
+        colors
+            .iter()
+            .enumerate()
+            .map(|(index, color)| match lines.contains(&(index / 16)) {
+                true => *color,
+                false => Color::BLACK,
+            })
+    };
+    let to_bgr555 = |color: Color| {
+        let [r, g, b, _] = color.into_rgba8();
+        bgr555::from_rgb8([r, g, b])
+    };
+    Ok(match format {
+        ExportFormat::Mw3 => {
+            let mut bytes = exported_colors()
+                .flat_map(|color| to_bgr555(color).to_le_bytes())
+                .collect::<Vec<u8>>();
+            // Color 0 is the back area color, which Lunar Magic keeps at the end. It's kept even
+            // when line 0 isn't exported.
+            bytes.extend(to_bgr555(colors[0]).to_le_bytes());
+            bytes
+        }
+        ExportFormat::Pal => exported_colors()
+            .flat_map(|color| {
+                let [r, g, b, _] = color.into_rgba8();
+                [r, g, b]
+            })
+            .collect(),
+        ExportFormat::Png => {
+            let line_colors = &colors[lines.start * 16..lines.end * 16];
+            let image = image::RgbImage::from_fn(16, lines.len() as u32, |x, y| {
+                let [r, g, b, _] = line_colors[y as usize * 16 + x as usize].into_rgba8();
+                image::Rgb([r, g, b])
+            });
+            let mut png_bytes = vec![];
+            image.write_to(
+                &mut std::io::Cursor::new(&mut png_bytes),
+                image::ImageFormat::Png,
+            )?;
+            png_bytes
+        }
+        ExportFormat::Asm => lines
+            .map(|line| {
+                let values = colors[line * 16..line * 16 + 16]
+                    .iter()
+                    .map(|color| format!("${:04X}", to_bgr555(*color)))
+                    .collect::<Vec<_>>();
+                format!("dw {} ; line {line:X}\n", values.join(","))
+            })
+            .collect::<String>()
+            .into_bytes(),
+    })
+}
+
+/// Asks where to save an exported palette, and writes it there.
+pub async fn export(bytes: Vec<u8>, format: ExportFormat) -> Option<PathBuf> {
+    let path = rfd::AsyncFileDialog::new()
+        .set_title("Export palette")
+        .add_filter(format.to_string(), &[format.extension()])
+        .set_file_name(format!("palette.{}", format.extension()))
+        .save_file()
+        .await?
+        .path()
+        .to_path_buf();
+    tokio::fs::write(&path, bytes)
+        .await
+        .inspect_err(|error| println!("Couldn't write {path:?}: {error}"))
+        .ok()?;
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guesses_pal_files_by_size() {
+        let guess = |len| PaletteFormat::guess(Path::new("level.pal"), len);
+        assert_eq!(guess(PaletteFormat::PAL_LEN), Some(PaletteFormat::Pal));
+        assert_eq!(guess(15 * 3), Some(PaletteFormat::Pal));
+        // 0x180 bytes is a multiple of 3, but it's 12 lines of BGR555.
+        assert_eq!(guess(0x180), Some(PaletteFormat::Bgr555));
+        assert_eq!(guess(COLOR_COUNT * 2), Some(PaletteFormat::Bgr555));
+        assert_eq!(guess(PaletteFormat::MW3_LEN), Some(PaletteFormat::Mw3));
+    }
+
+    #[test]
+    fn mw3_keeps_the_back_area_color() {
+        let mut colors = vec![Color::BLACK; COLOR_COUNT];
+        colors[0] = Color::from_rgb8(0x00, 0x80, 0xF8);
+        colors[16] = Color::WHITE;
+        let bytes = encode(&colors, 1..2, ExportFormat::Mw3).unwrap();
+        assert_eq!(bytes.len(), PaletteFormat::MW3_LEN);
+        // Line 0 isn't exported, but the back area color at the end is still color 0.
+        assert_eq!(bytes[..2], [0, 0]);
+        assert_eq!(bytes[32..34], 0x7FFFu16.to_le_bytes());
+        assert_eq!(
+            bytes[COLOR_COUNT * 2..],
+            bgr555::from_rgb8([0x00, 0x80, 0xF8]).to_le_bytes()
+        );
+    }
+}
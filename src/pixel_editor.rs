@@ -0,0 +1,615 @@
+//! A zoomed in editor for the pixels of a tile, or of a 16x16 quad of tiles. It only knows about
+//! color indices; the parent decides which tiles they come from and writes them back.
+
+use iced::{
+    event::Status,
+    mouse::{self, Cursor},
+    widget::canvas::{self, Event},
+    Border, Color, Element, Point, Rectangle, Renderer, Size,
+};
+
+/// How big the editor is on screen, whatever size of tile it's editing.
+const CANVAS_SIZE: f32 = 192.;
+const SWATCH_SIZE: f32 = 16.;
+const SWATCHES_PER_ROW: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tool {
+    #[default]
+    Pencil,
+    Line,
+    Rectangle,
+    Fill,
+    /// Swaps every pixel of the clicked pixel's color for the drawing color.
+    ReplaceColor,
+}
+impl Tool {
+    pub const ALL: [Tool; 5] = [
+        Self::Pencil,
+        Self::Line,
+        Self::Rectangle,
+        Self::Fill,
+        Self::ReplaceColor,
+    ];
+}
+impl std::fmt::Display for Tool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Pencil => "Pencil",
+            Self::Line => "Line",
+            Self::Rectangle => "Rectangle",
+            Self::Fill => "Fill",
+            Self::ReplaceColor => "Replace color",
+        })
+    }
+}
+
+/// These are messages that parent is expected to want to handle.
+#[derive(Debug, Clone, Copy)]
+pub enum PublicMessage {
+    /// Raised whenever the pixels change, so that the parent can write them back to its tiles.
+    PixelsEdited,
+    /// Raised when a swatch is picked as the primary or secondary color.
+    ColorsPicked,
+}
+
+/// Parent of this component should pass this Envelope to the Component::update function, which may return a PublicMessage.
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope(PrivateMessage);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PixelCoords(usize, usize);
+
+/// The left mouse button draws with the primary color, and the right with the secondary color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorButton {
+    Primary,
+    Secondary,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PrivateMessage {
+    ButtonPressedOnPixel(PixelCoords, ColorButton),
+    CursorMovedOverPixel(PixelCoords),
+    ButtonReleased,
+    ToolSelected(Tool),
+    SwatchPressed(u8, ColorButton),
+}
+
+/// A press on the canvas that hasn't been released yet.
+#[derive(Debug, Clone, Copy)]
+struct Stroke {
+    start: PixelCoords,
+    last: PixelCoords,
+    color: u8,
+}
+
+pub struct Component {
+    tool: Tool,
+    primary_color: u8,
+    secondary_color: u8,
+    /// The color indices that can be picked to draw with.
+    swatches: Vec<u8>,
+    canvas: PixelCanvas,
+    stroke: Option<Stroke>,
+}
+impl Component {
+    pub fn new() -> Self {
+        Self {
+            tool: Tool::default(),
+            primary_color: 1,
+            secondary_color: 0,
+            swatches: vec![],
+            canvas: PixelCanvas {
+                cache: canvas::Cache::default(),
+                pixels: PixelGrid::new(8, 8),
+                preview: None,
+                colors: vec![],
+            },
+            stroke: None,
+        }
+    }
+
+    /// Replaces the pixels being edited, abandoning any stroke in progress.
+    pub fn set_pixels(&mut self, width: usize, height: usize, pixels: Vec<u8>) {
+        assert_eq!(pixels.len(), width * height);
+        self.canvas.pixels = PixelGrid {
+            width,
+            height,
+            pixels,
+        };
+        self.canvas.preview = None;
+        self.stroke = None;
+        self.canvas.cache.clear();
+    }
+
+    /// The color indices being edited, row by row.
+    pub fn get_pixels(&self) -> &[u8] {
+        &self.canvas.pixels.pixels
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.canvas.pixels.width
+    }
+
+    /// `colors` are what each color index looks like, and `swatches` are the color indices that can
+    /// be drawn with.
+    pub fn set_colors(&mut self, colors: Vec<Color>, swatches: Vec<u8>) {
+        if !swatches.contains(&self.primary_color) {
+            self.primary_color = swatches.get(1).copied().unwrap_or(0);
+        }
+        if !swatches.contains(&self.secondary_color) {
+            self.secondary_color = swatches.first().copied().unwrap_or(0);
+        }
+        self.swatches = swatches;
+        self.canvas.colors = colors;
+        self.canvas.cache.clear();
+    }
+
+    /// The color indices drawn with the left and right mouse buttons.
+    pub fn get_colors(&self) -> (u8, u8) {
+        (self.primary_color, self.secondary_color)
+    }
+
+    /// Picks the color index to draw with using the left mouse button, if it's a swatch.
+    pub fn set_primary_color(&mut self, color: u8) {
+        if self.swatches.contains(&color) {
+            self.primary_color = color;
+        }
+    }
+
+    /// Picks the color index to draw with using the right mouse button, if it's a swatch.
+    pub fn set_secondary_color(&mut self, color: u8) {
+        if self.swatches.contains(&color) {
+            self.secondary_color = color;
+        }
+    }
+
+    pub fn update(&mut self, envelope: Envelope) -> Option<PublicMessage> {
+        match envelope.0 {
+            PrivateMessage::ButtonPressedOnPixel(pixel, button) => {
+                let color = match button {
+                    ColorButton::Primary => self.primary_color,
+                    ColorButton::Secondary => self.secondary_color,
+                };
+                self.stroke = Some(Stroke {
+                    start: pixel,
+                    last: pixel,
+                    color,
+                });
+                match self.tool {
+                    Tool::Pencil => self.edit(|pixels| pixels.set(pixel, color)),
+                    Tool::Line | Tool::Rectangle => {
+                        self.preview_stroke();
+                        None
+                    }
+                    Tool::Fill => self.edit(|pixels| pixels.flood_fill(pixel, color)),
+                    Tool::ReplaceColor => self.edit(|pixels| {
+                        let replaced_color = pixels.get(pixel);
+                        pixels.replace_color(replaced_color, color)
+                    }),
+                }
+            }
+            PrivateMessage::CursorMovedOverPixel(pixel) => {
+                let stroke = self.stroke.as_mut()?;
+                let previous_pixel = stroke.last;
+                let color = stroke.color;
+                stroke.last = pixel;
+                match self.tool {
+                    Tool::Pencil => {
+                        self.edit(|pixels| pixels.draw_line(previous_pixel, pixel, color))
+                    }
+                    Tool::Line | Tool::Rectangle => {
+                        self.preview_stroke();
+                        None
+                    }
+                    Tool::Fill | Tool::ReplaceColor => None,
+                }
+            }
+            PrivateMessage::ButtonReleased => {
+                self.stroke.take()?;
+                let preview = self.canvas.preview.take()?;
+                self.edit(|pixels| *pixels = preview)
+            }
+            PrivateMessage::ToolSelected(tool) => {
+                self.tool = tool;
+                self.stroke = None;
+                self.canvas.preview = None;
+                self.canvas.cache.clear();
+                None
+            }
+            PrivateMessage::SwatchPressed(color, ColorButton::Primary) => {
+                self.primary_color = color;
+                Some(PublicMessage::ColorsPicked)
+            }
+            PrivateMessage::SwatchPressed(color, ColorButton::Secondary) => {
+                self.secondary_color = color;
+                Some(PublicMessage::ColorsPicked)
+            }
+        }
+    }
+
+    /// Applies an edit to the pixels, only raising PixelsEdited if it changed any of them.
+    fn edit(&mut self, edit: impl FnOnce(&mut PixelGrid)) -> Option<PublicMessage> {
+        let pixels_before = self.canvas.pixels.pixels.clone();
+        edit(&mut self.canvas.pixels);
+        self.canvas.cache.clear();
+        (self.canvas.pixels.pixels != pixels_before).then_some(PublicMessage::PixelsEdited)
+    }
+
+    /// Draws the line or rectangle being dragged out over a copy of the pixels, which only replaces
+    /// them once the button is released.
+    fn preview_stroke(&mut self) {
+        let Some(stroke) = self.stroke else {
+            return;
+        };
+        let mut preview = self.canvas.pixels.clone();
+        match self.tool {
+            Tool::Line => preview.draw_line(stroke.start, stroke.last, stroke.color),
+            Tool::Rectangle => preview.draw_rectangle(stroke.start, stroke.last, stroke.color),
+            _ => return,
+        }
+        self.canvas.preview = Some(preview);
+        self.canvas.cache.clear();
+    }
+
+    pub fn view(&self) -> Element<'_, Envelope> {
+        use iced::widget::{column, row, *};
+
+        let swatch = |color_index: u8| {
+            let color = self
+                .canvas
+                .colors
+                .get(color_index as usize)
+                .copied()
+                .unwrap_or(Color::BLACK);
+            let border_color = if color_index == self.primary_color {
+                Some(Color::WHITE)
+            } else if color_index == self.secondary_color {
+                Some(Color::new(0.5, 0.5, 0.5, 1.))
+            } else {
+                None
+            };
+            mouse_area(
+                container(Space::new(SWATCH_SIZE, SWATCH_SIZE)).style(move |_| container::Style {
+                    background: Some(color.into()),
+                    border: Border {
+                        color: border_color.unwrap_or(Color::TRANSPARENT),
+                        width: 2.,
+                        radius: 0.into(),
+                    },
+                    ..Default::default()
+                }),
+            )
+            .on_press(Envelope(PrivateMessage::SwatchPressed(
+                color_index,
+                ColorButton::Primary,
+            )))
+            .on_right_press(Envelope(PrivateMessage::SwatchPressed(
+                color_index,
+                ColorButton::Secondary,
+            )))
+            .into()
+        };
+
+        column![
+            row![
+                canvas(&self.canvas).width(CANVAS_SIZE).height(CANVAS_SIZE),
+                column(Tool::ALL.into_iter().map(|tool| {
+                    button(text(tool.to_string()))
+                        .style(if tool == self.tool {
+                            button::primary
+                        } else {
+                            button::secondary
+                        })
+                        .on_press(Envelope(PrivateMessage::ToolSelected(tool)))
+                        .into()
+                }))
+                .spacing(5),
+            ]
+            .spacing(10),
+            column(
+                self.swatches
+                    .chunks(SWATCHES_PER_ROW)
+                    .map(|swatches_in_row| {
+                        row(swatches_in_row
+                            .iter()
+                            .map(|color_index| swatch(*color_index)))
+                        .into()
+                    })
+            ),
+        ]
+        .spacing(10)
+        .into()
+    }
+}
+
+/// Color indices, row by row.
+#[derive(Debug, Clone)]
+struct PixelGrid {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+impl PixelGrid {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    fn get(&self, pixel: PixelCoords) -> u8 {
+        self.pixels[pixel.1 * self.width + pixel.0]
+    }
+
+    fn set(&mut self, pixel: PixelCoords, color: u8) {
+        self.pixels[pixel.1 * self.width + pixel.0] = color;
+    }
+
+    /// Bresenham's line, including both ends.
+    fn draw_line(&mut self, from: PixelCoords, to: PixelCoords, color: u8) {
+        let (mut x, mut y) = (from.0 as isize, from.1 as isize);
+        let (to_x, to_y) = (to.0 as isize, to.1 as isize);
+        let dx = (to_x - x).abs();
+        let dy = -(to_y - y).abs();
+        let step_x = if x < to_x { 1 } else { -1 };
+        let step_y = if y < to_y { 1 } else { -1 };
+        let mut error = dx + dy;
+        loop {
+            self.set(PixelCoords(x as usize, y as usize), color);
+            if x == to_x && y == to_y {
+                break;
+            }
+            let doubled_error = 2 * error;
+            if doubled_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws the outline of the rectangle with the two pixels as opposite corners.
+    fn draw_rectangle(&mut self, corner: PixelCoords, opposite_corner: PixelCoords, color: u8) {
+        let (left, right) = (
+            corner.0.min(opposite_corner.0),
+            corner.0.max(opposite_corner.0),
+        );
+        let (top, bottom) = (
+            corner.1.min(opposite_corner.1),
+            corner.1.max(opposite_corner.1),
+        );
+        for x in left..=right {
+            self.set(PixelCoords(x, top), color);
+            self.set(PixelCoords(x, bottom), color);
+        }
+        for y in top..=bottom {
+            self.set(PixelCoords(left, y), color);
+            self.set(PixelCoords(right, y), color);
+        }
+    }
+
+    /// Fills the area of same colored pixels that `start` is in, not counting diagonal neighbors.
+    fn flood_fill(&mut self, start: PixelCoords, color: u8) {
+        let replaced_color = self.get(start);
+        if replaced_color == color {
+            return;
+        }
+        let mut pixels_to_fill = vec![start];
+        while let Some(pixel) = pixels_to_fill.pop() {
+            if self.get(pixel) != replaced_color {
+                continue;
+            }
+            self.set(pixel, color);
+            let PixelCoords(x, y) = pixel;
+            if x > 0 {
+                pixels_to_fill.push(PixelCoords(x - 1, y));
+            }
+            if x + 1 < self.width {
+                pixels_to_fill.push(PixelCoords(x + 1, y));
+            }
+            if y > 0 {
+                pixels_to_fill.push(PixelCoords(x, y - 1));
+            }
+            if y + 1 < self.height {
+                pixels_to_fill.push(PixelCoords(x, y + 1));
+            }
+        }
+    }
+
+    fn replace_color(&mut self, replaced_color: u8, color: u8) {
+        self.pixels
+            .iter_mut()
+            .filter(|pixel| **pixel == replaced_color)
+            .for_each(|pixel| *pixel = color);
+    }
+}
+
+struct PixelCanvas {
+    cache: canvas::Cache,
+    pixels: PixelGrid,
+    /// The pixels with the line or rectangle being dragged out drawn on them.
+    preview: Option<PixelGrid>,
+    /// What each color index looks like.
+    colors: Vec<Color>,
+}
+impl PixelCanvas {
+    fn pixel_at(&self, bounds: Rectangle, cursor: Cursor) -> Option<PixelCoords> {
+        let point = cursor.position_in(bounds)?;
+        Some(PixelCoords(
+            ((point.x / bounds.width * self.pixels.width as f32) as usize)
+                .min(self.pixels.width - 1),
+            ((point.y / bounds.height * self.pixels.height as f32) as usize)
+                .min(self.pixels.height - 1),
+        ))
+    }
+}
+impl canvas::Program<Envelope> for PixelCanvas {
+    /// The pixel last reported as hovered, so that moving within a pixel doesn't raise messages.
+    type State = Option<PixelCoords>;
+
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: Event,
+        bounds: Rectangle,
+        cursor: Cursor,
+    ) -> (Status, Option<Envelope>) {
+        let pressed = |button| match self.pixel_at(bounds, cursor) {
+            Some(pixel) => (
+                Status::Captured,
+                Some(Envelope(PrivateMessage::ButtonPressedOnPixel(
+                    pixel, button,
+                ))),
+            ),
+            None => (Status::Ignored, None),
+        };
+        match event {
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Left)) => {
+                pressed(ColorButton::Primary)
+            }
+            Event::Mouse(mouse::Event::ButtonPressed(mouse::Button::Right)) => {
+                pressed(ColorButton::Secondary)
+            }
+            // Released anywhere, so that strokes dragged off the canvas still finish.
+            Event::Mouse(mouse::Event::ButtonReleased(
+                mouse::Button::Left | mouse::Button::Right,
+            )) => (
+                Status::Ignored,
+                Some(Envelope(PrivateMessage::ButtonReleased)),
+            ),
+            Event::Mouse(mouse::Event::CursorMoved { .. }) => match self.pixel_at(bounds, cursor) {
+                Some(pixel) if *state != Some(pixel) => {
+                    *state = Some(pixel);
+                    (
+                        Status::Captured,
+                        Some(Envelope(PrivateMessage::CursorMovedOverPixel(pixel))),
+                    )
+                }
+                _ => (Status::Ignored, None),
+            },
+            _ => (Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        _theme: &iced::Theme,
+        bounds: Rectangle,
+        _cursor: Cursor,
+    ) -> Vec<canvas::Geometry<Renderer>> {
+        vec![self.cache.draw(renderer, bounds.size(), |frame| {
+            let pixels = self.preview.as_ref().unwrap_or(&self.pixels);
+            let pixel_size = Size::new(
+                bounds.width / pixels.width as f32,
+                bounds.height / pixels.height as f32,
+            );
+            for y in 0..pixels.height {
+                for x in 0..pixels.width {
+                    let top_left =
+                        Point::new(x as f32 * pixel_size.width, y as f32 * pixel_size.height);
+                    let color_index = pixels.get(PixelCoords(x, y));
+                    if color_index == 0 {
+                        // Color 0 is transparent, so show a checkerboard through it.
+                        let half_size = Size::new(pixel_size.width / 2., pixel_size.height / 2.);
+                        frame.fill_rectangle(top_left, pixel_size, Color::new(0.4, 0.4, 0.4, 1.));
+                        frame.fill_rectangle(top_left, half_size, Color::new(0.6, 0.6, 0.6, 1.));
+                        frame.fill_rectangle(
+                            Point::new(top_left.x + half_size.width, top_left.y + half_size.height),
+                            half_size,
+                            Color::new(0.6, 0.6, 0.6, 1.),
+                        );
+                    } else {
+                        let color = self
+                            .colors
+                            .get(color_index as usize)
+                            .copied()
+                            .unwrap_or(Color::BLACK);
+                        frame.fill_rectangle(top_left, pixel_size, color);
+                    }
+                }
+            }
+
+            // Outline each tile when editing a quad.
+            for tile_y in (0..pixels.height).step_by(8).skip(1) {
+                frame.fill_rectangle(
+                    Point::new(0., tile_y as f32 * pixel_size.height - 0.5),
+                    Size::new(bounds.width, 1.),
+                    Color::new(0.9, 0.9, 0.9, 0.5),
+                );
+            }
+            for tile_x in (0..pixels.width).step_by(8).skip(1) {
+                frame.fill_rectangle(
+                    Point::new(tile_x as f32 * pixel_size.width - 0.5, 0.),
+                    Size::new(1., bounds.height),
+                    Color::new(0.9, 0.9, 0.9, 0.5),
+                );
+            }
+        })]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid from rows of hex digits, one per pixel.
+    fn grid(rows: &[&str]) -> PixelGrid {
+        PixelGrid {
+            width: rows[0].len(),
+            height: rows.len(),
+            pixels: rows
+                .iter()
+                .flat_map(|row| row.chars().map(|c| c.to_digit(16).unwrap() as u8))
+                .collect(),
+        }
+    }
+
+    fn rows(grid: &PixelGrid) -> Vec<String> {
+        grid.pixels
+            .chunks(grid.width)
+            .map(|row| row.iter().map(|pixel| format!("{pixel:X}")).collect())
+            .collect()
+    }
+
+    #[test]
+    fn draws_lines_including_both_ends() {
+        let mut pixels = PixelGrid::new(5, 4);
+        pixels.draw_line(PixelCoords(0, 0), PixelCoords(4, 2), 1);
+        pixels.draw_line(PixelCoords(4, 3), PixelCoords(1, 3), 2);
+        assert_eq!(rows(&pixels), ["10000", "01100", "00011", "02222"]);
+    }
+
+    #[test]
+    fn draws_rectangle_outlines_from_any_corner() {
+        let mut pixels = PixelGrid::new(5, 4);
+        pixels.draw_rectangle(PixelCoords(3, 3), PixelCoords(0, 1), 7);
+        assert_eq!(rows(&pixels), ["00000", "77770", "70070", "77770"]);
+    }
+
+    #[test]
+    fn flood_fills_without_crossing_diagonals() {
+        let mut pixels = grid(&["0010", "0100", "1000", "0001"]);
+        pixels.flood_fill(PixelCoords(0, 0), 3);
+        assert_eq!(rows(&pixels), ["3310", "3100", "1000", "0001"]);
+        pixels.flood_fill(PixelCoords(3, 0), 4);
+        assert_eq!(rows(&pixels), ["3314", "3144", "1444", "4441"]);
+        // Filling with the same color changes nothing.
+        pixels.flood_fill(PixelCoords(3, 0), 4);
+        assert_eq!(rows(&pixels), ["3314", "3144", "1444", "4441"]);
+    }
+
+    #[test]
+    fn replaces_a_color_everywhere() {
+        let mut pixels = grid(&["0120", "2010"]);
+        pixels.replace_color(0, 5);
+        assert_eq!(rows(&pixels), ["5125", "2515"]);
+    }
+}
@@ -0,0 +1,121 @@
+//! Converting between SNES planar tiles and one color index per pixel on the CPU, laid out the same
+//! way tilemap_shader.wgsl reads them on the GPU.
+
+use crate::tilemap::BitDepth;
+
+pub const PIXELS_PER_TILE_ROW: usize = 8;
+pub const PIXELS_PER_TILE: usize = PIXELS_PER_TILE_ROW * PIXELS_PER_TILE_ROW;
+
+/// Where a row of one bitplane is within a tile. Bitplanes are stored in pairs: each pair is 16
+/// bytes, with the two planes of a row interleaved. SMW's 3bpp format is the exception, where the
+/// third plane comes on its own after the first pair, one byte per row.
+fn plane_row_address(bit_depth: BitDepth, plane: usize, row: usize) -> usize {
+    if bit_depth == BitDepth::Bpp3 && plane == 2 {
+        16 + row
+    } else {
+        (plane / 2) * 16 + row * 2 + (plane & 1)
+    }
+}
+
+/// Reads a tile's color indices, row by row. Missing bytes at the end of a file read as zero.
+pub fn decode_tile(tile_bytes: &[u8], bit_depth: BitDepth) -> [u8; PIXELS_PER_TILE] {
+    let mut pixels = [0; PIXELS_PER_TILE];
+    for plane in 0..bit_depth.bits_per_pixel() {
+        for row in 0..PIXELS_PER_TILE_ROW {
+            let line = tile_bytes
+                .get(plane_row_address(bit_depth, plane, row))
+                .copied()
+                .unwrap_or(0);
+            for column in 0..PIXELS_PER_TILE_ROW {
+                pixels[row * PIXELS_PER_TILE_ROW + column] |= ((line >> (7 - column)) & 1) << plane;
+            }
+        }
+    }
+    pixels
+}
+
+/// Writes color indices, row by row, as a tile. Bits of the indices that don't fit in the bit depth
+/// are dropped.
+pub fn encode_tile(pixels: &[u8; PIXELS_PER_TILE], bit_depth: BitDepth) -> Vec<u8> {
+    let mut tile_bytes = vec![0; bit_depth.bytes_per_tile()];
+    for plane in 0..bit_depth.bits_per_pixel() {
+        for row in 0..PIXELS_PER_TILE_ROW {
+            tile_bytes[plane_row_address(bit_depth, plane, row)] =
+                (0..PIXELS_PER_TILE_ROW).fold(0, |line, column| {
+                    line | ((pixels[row * PIXELS_PER_TILE_ROW + column] >> plane) & 1)
+                        << (7 - column)
+                });
+        }
+    }
+    tile_bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every color index the bit depth has, spread over the tile so that each plane varies.
+    fn test_pixels(bit_depth: BitDepth) -> [u8; PIXELS_PER_TILE] {
+        let color_count = 1usize << bit_depth.bits_per_pixel();
+        std::array::from_fn(|pixel| ((pixel * 37 + pixel / 8) % color_count) as u8)
+    }
+
+    #[test]
+    fn round_trips_every_bit_depth() {
+        for bit_depth in BitDepth::ALL {
+            let pixels = test_pixels(bit_depth);
+            let tile_bytes = encode_tile(&pixels, bit_depth);
+            assert_eq!(tile_bytes.len(), bit_depth.bytes_per_tile(), "{bit_depth}");
+            assert_eq!(decode_tile(&tile_bytes, bit_depth), pixels, "{bit_depth}");
+        }
+    }
+
+    #[test]
+    fn interleaves_plane_pairs() {
+        let mut pixels = [0; PIXELS_PER_TILE];
+        // The top left pixel has every plane set, and the pixel right of it only plane 1.
+        pixels[0] = 0xFF;
+        pixels[1] = 0b10;
+        let tile_bytes = encode_tile(&pixels, BitDepth::Bpp8);
+        for pair in 0..4 {
+            assert_eq!(tile_bytes[pair * 16], 0b1000_0000, "plane {}", pair * 2);
+            let expected = if pair == 0 { 0b1100_0000 } else { 0b1000_0000 };
+            assert_eq!(
+                tile_bytes[pair * 16 + 1],
+                expected,
+                "plane {}",
+                pair * 2 + 1
+            );
+        }
+        assert_eq!(tile_bytes.iter().filter(|byte| **byte != 0).count(), 8);
+    }
+
+    #[test]
+    fn puts_the_third_3bpp_plane_after_the_first_pair() {
+        let mut pixels = [0; PIXELS_PER_TILE];
+        // The last pixel of row 5 only has plane 2 set.
+        pixels[5 * PIXELS_PER_TILE_ROW + 7] = 0b100;
+        let mut expected = vec![0; 24];
+        expected[16 + 5] = 1;
+        assert_eq!(encode_tile(&pixels, BitDepth::Bpp3), expected);
+    }
+
+    #[test]
+    fn drops_bits_that_dont_fit() {
+        let pixels = [0b111; PIXELS_PER_TILE];
+        let tile_bytes = encode_tile(&pixels, BitDepth::Bpp2);
+        assert_eq!(
+            decode_tile(&tile_bytes, BitDepth::Bpp2),
+            [0b11; PIXELS_PER_TILE]
+        );
+    }
+
+    #[test]
+    fn reads_missing_bytes_as_zero() {
+        let pixels = test_pixels(BitDepth::Bpp4);
+        let tile_bytes = encode_tile(&pixels, BitDepth::Bpp4);
+        // Without the second pair of planes, only the first two planes are left.
+        let decoded = decode_tile(&tile_bytes[..16], BitDepth::Bpp4);
+        assert_eq!(decoded, pixels.map(|pixel| pixel & 0b11));
+    }
+}
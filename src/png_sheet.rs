@@ -0,0 +1,375 @@
+//! Exporting graphics as indexed PNG sheets, so that they can be edited in ordinary image editors,
+//! and importing them back.
+
+use std::{path::PathBuf, sync::Arc};
+
+use iced::Color;
+
+use crate::{planar, tilemap::BitDepth};
+
+/// Sheets are 16 tiles wide, in either layout.
+const TILES_PER_ROW: usize = 16;
+
+/// How tiles are arranged in a sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SheetLayout {
+    /// 2x2 quads of tiles, 8 quads to a row, the way the graphics file view shows them.
+    #[default]
+    Quads,
+    /// One tile after another, 16 to a row, the way YY-CHR shows them.
+    Linear,
+}
+impl SheetLayout {
+    pub const ALL: [SheetLayout; 2] = [Self::Quads, Self::Linear];
+
+    /// Where a tile goes in a sheet that's the given number of tiles wide, in tiles.
+    fn tile_position(self, tile_index: usize, tiles_per_row: usize) -> (usize, usize) {
+        match self {
+            Self::Quads => {
+                let quads_per_row = tiles_per_row / 2;
+                let quad_index = tile_index / 4;
+                let tile_index_in_quad = tile_index % 4;
+                (
+                    quad_index % quads_per_row * 2 + tile_index_in_quad % 2,
+                    quad_index / quads_per_row * 2 + tile_index_in_quad / 2,
+                )
+            }
+            Self::Linear => (tile_index % tiles_per_row, tile_index / tiles_per_row),
+        }
+    }
+
+    /// How many tiles fit in a sheet of the given size, in tiles. Quads that would be cut off by
+    /// the edge of the sheet don't count.
+    fn tile_count(self, width_in_tiles: usize, height_in_tiles: usize) -> usize {
+        match self {
+            Self::Quads => (width_in_tiles / 2) * (height_in_tiles / 2) * 4,
+            Self::Linear => width_in_tiles * height_in_tiles,
+        }
+    }
+}
+impl std::fmt::Display for SheetLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Quads => "16x16 quads",
+            Self::Linear => "Linear",
+        })
+    }
+}
+
+/// What color 0 of every tile becomes in a sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorZero {
+    #[default]
+    Transparent,
+    /// The first color of the palette, which is what shows through every layer on the SNES.
+    BackAreaColor,
+}
+impl ColorZero {
+    pub const ALL: [ColorZero; 2] = [Self::Transparent, Self::BackAreaColor];
+}
+impl std::fmt::Display for ColorZero {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Transparent => "Transparent color 0",
+            Self::BackAreaColor => "Back area color 0",
+        })
+    }
+}
+
+async fn pick_png_to_save(file_name: String) -> Option<PathBuf> {
+    rfd::AsyncFileDialog::new()
+        .set_title("Export PNG")
+        .add_filter("PNG image", &["png"])
+        .set_file_name(file_name)
+        .save_file()
+        .await
+        .map(|file_handle| file_handle.path().to_path_buf())
+}
+
+/// Encodes graphics as an indexed PNG, with one palette entry per color a tile of the bit depth can
+/// use. `palette` is the whole palette, and `first_color` is the index into it of the tiles' color
+/// 0. A partial tile at the end of the bytes is left out.
+pub fn encode(
+    graphics_bytes: &[u8],
+    bit_depth: BitDepth,
+    layout: SheetLayout,
+    palette: &[Color],
+    first_color: usize,
+    color_zero: ColorZero,
+) -> Result<Vec<u8>, png::EncodingError> {
+    let tile_count = graphics_bytes.len() / bit_depth.bytes_per_tile();
+    let rows = (0..tile_count)
+        .map(|tile_index| layout.tile_position(tile_index, TILES_PER_ROW).1 + 1)
+        .max()
+        .unwrap_or(1);
+    let width = TILES_PER_ROW * planar::PIXELS_PER_TILE_ROW;
+    let height = rows * planar::PIXELS_PER_TILE_ROW;
+
+    let mut pixels = vec![0; width * height];
+    for (tile_index, tile_bytes) in graphics_bytes
+        .chunks_exact(bit_depth.bytes_per_tile())
+        .enumerate()
+    {
+        let (tile_x, tile_y) = layout.tile_position(tile_index, TILES_PER_ROW);
+        let tile_pixels = planar::decode_tile(tile_bytes, bit_depth);
+        for (row, row_pixels) in tile_pixels.chunks(planar::PIXELS_PER_TILE_ROW).enumerate() {
+            let start = (tile_y * planar::PIXELS_PER_TILE_ROW + row) * width
+                + tile_x * planar::PIXELS_PER_TILE_ROW;
+            pixels[start..start + planar::PIXELS_PER_TILE_ROW].copy_from_slice(row_pixels);
+        }
+    }
+
+    let png_palette = (0..1usize << bit_depth.bits_per_pixel())
+        .flat_map(|color_index| {
+            let color = match (color_index, color_zero) {
+                (0, ColorZero::BackAreaColor) => palette.first(),
+                _ => palette.get(first_color + color_index),
+            };
+            let [r, g, b, _] = color.copied().unwrap_or(Color::BLACK).into_rgba8();
+            [r, g, b]
+        })
+        .collect::<Vec<u8>>();
+
+    let mut png_bytes = vec![];
+    let mut encoder = png::Encoder::new(&mut png_bytes, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(png_palette);
+    if color_zero == ColorZero::Transparent {
+        // Entries past the end of the transparency chunk are opaque.
+        encoder.set_trns(vec![0]);
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+    Ok(png_bytes)
+}
+
+/// Asks where to save a sheet, then saves it there.
+pub async fn export(file_name: String, png_bytes: Vec<u8>) -> Option<PathBuf> {
+    let path = pick_png_to_save(file_name).await?;
+    tokio::fs::write(&path, png_bytes)
+        .await
+        .inspect_err(|error| println!("Couldn't write {path:?}: {error}"))
+        .ok()?;
+    Some(path)
+}
+
+pub async fn pick_png_to_import() -> Option<PathBuf> {
+    rfd::AsyncFileDialog::new()
+        .set_title("Import PNG")
+        .add_filter("PNG image", &["png"])
+        .pick_file()
+        .await
+        .map(|file_handle| file_handle.path().to_path_buf())
+}
+
+/// Reads a PNG of any color type, indexed or not, as RGBA.
+pub async fn load(path: PathBuf) -> Option<(PathBuf, Arc<image::RgbaImage>)> {
+    let contents = tokio::fs::read(&path)
+        .await
+        .inspect_err(|error| println!("Couldn't read {path:?}: {error}"))
+        .ok()?;
+    let image = image::load_from_memory_with_format(&contents, image::ImageFormat::Png)
+        .inspect_err(|error| println!("Couldn't decode {path:?}: {error}"))
+        .ok()?;
+    Some((path, Arc::new(image.to_rgba8())))
+}
+
+/// How well an imported image's colors matched the palette.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub tile_count: usize,
+    /// Pixels whose color wasn't in the palette, and were given the nearest color that was.
+    pub inexact_pixel_count: usize,
+    /// The biggest distance between a pixel's color and the color it was given, as the square of
+    /// the distance between their 8-bit RGB values.
+    pub worst_squared_distance: u32,
+    /// Where in the image the pixel with the worst match is, in pixels from the top left.
+    pub worst_pixel: Option<(usize, usize)>,
+}
+impl std::fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} tiles", self.tile_count)?;
+        if self.inexact_pixel_count > 0 {
+            write!(
+                f,
+                ", {} pixels got the nearest color in the palette (worst distance {:.1}",
+                self.inexact_pixel_count,
+                (self.worst_squared_distance as f32).sqrt()
+            )?;
+            if let Some((x, y)) = self.worst_pixel {
+                write!(f, " at {x}, {y}")?;
+            }
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Slices an image into tiles in the given layout and encodes them at the given bit depth. Each
+/// pixel becomes whichever of the colors a tile can use it matches, or the nearest one. Transparent
+/// pixels become color 0, and so do pixels of the back area color that no color of the line
+/// matches, so that sheets exported either way import back the same. Edges of the image that don't
+/// make a whole tile, or quad, are left out.
+pub fn decode(
+    image: &image::RgbaImage,
+    bit_depth: BitDepth,
+    layout: SheetLayout,
+    palette: &[Color],
+    first_color: usize,
+) -> (Vec<u8>, ImportReport) {
+    let to_rgb = |color: Color| {
+        let [r, g, b, _] = color.into_rgba8();
+        [r, g, b]
+    };
+    let back_area_color = palette.first().copied().map(to_rgb);
+    // Color 0 is transparent, so it's never matched.
+    let colors = (1..1usize << bit_depth.bits_per_pixel())
+        .map(|color_index| {
+            let color = palette
+                .get(first_color + color_index)
+                .copied()
+                .unwrap_or(Color::BLACK);
+            (color_index as u8, to_rgb(color))
+        })
+        .collect::<Vec<_>>();
+
+    let mut report = ImportReport::default();
+    let mut color_index_of_pixel = |x: usize, y: usize| {
+        let [r, g, b, a] = image.get_pixel(x as u32, y as u32).0;
+        if a < 0x80 {
+            return 0;
+        }
+        let (color_index, squared_distance) = colors
+            .iter()
+            .map(|(color_index, color)| {
+                let squared_distance = [r, g, b]
+                    .iter()
+                    .zip(color)
+                    .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+                    .sum::<u32>();
+                (*color_index, squared_distance)
+            })
+            .min_by_key(|(_, squared_distance)| *squared_distance)
+            .unwrap_or((0, 0));
+        // The line's own colors come first, even when one of them is the back area color.
+        if squared_distance > 0 && Some([r, g, b]) == back_area_color {
+            return 0;
+        }
+        if squared_distance > 0 {
+            report.inexact_pixel_count += 1;
+            if squared_distance > report.worst_squared_distance {
+                report.worst_squared_distance = squared_distance;
+                report.worst_pixel = Some((x, y));
+            }
+        }
+        color_index
+    };
+
+    let width_in_tiles = image.width() as usize / planar::PIXELS_PER_TILE_ROW;
+    let height_in_tiles = image.height() as usize / planar::PIXELS_PER_TILE_ROW;
+    let tile_count = layout.tile_count(width_in_tiles, height_in_tiles);
+    let mut graphics_bytes = Vec::with_capacity(tile_count * bit_depth.bytes_per_tile());
+    for tile_index in 0..tile_count {
+        let (tile_x, tile_y) = layout.tile_position(tile_index, width_in_tiles);
+        let mut tile_pixels = [0; planar::PIXELS_PER_TILE];
+        for (pixel_index, tile_pixel) in tile_pixels.iter_mut().enumerate() {
+            let x =
+                tile_x * planar::PIXELS_PER_TILE_ROW + pixel_index % planar::PIXELS_PER_TILE_ROW;
+            let y =
+                tile_y * planar::PIXELS_PER_TILE_ROW + pixel_index / planar::PIXELS_PER_TILE_ROW;
+            *tile_pixel = color_index_of_pixel(x, y);
+        }
+        graphics_bytes.extend(planar::encode_tile(&tile_pixels, bit_depth));
+    }
+    report.tile_count = tile_count;
+    (graphics_bytes, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A palette where every color is different, except that color 5 of line 1 is the back area
+    /// color.
+    fn palette() -> Vec<Color> {
+        let mut palette = (0..256u16)
+            .map(|index| crate::bgr555::to_color(index * 0x7F))
+            .collect::<Vec<_>>();
+        palette[0x15] = palette[0];
+        palette
+    }
+
+    fn graphics_bytes() -> Vec<u8> {
+        (0..8)
+            .flat_map(|tile| {
+                let mut pixels = [0; planar::PIXELS_PER_TILE];
+                for (pixel_index, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = ((pixel_index * 7 + tile * 3) % 16) as u8;
+                }
+                planar::encode_tile(&pixels, BitDepth::Bpp4)
+            })
+            .collect()
+    }
+
+    fn round_trip(layout: SheetLayout, color_zero: ColorZero, palette: &[Color]) -> Vec<u8> {
+        let bytes = graphics_bytes();
+        let png_bytes = encode(&bytes, BitDepth::Bpp4, layout, palette, 0x10, color_zero).unwrap();
+        let image = image::load_from_memory(&png_bytes).unwrap().to_rgba8();
+        let (decoded_bytes, report) = decode(&image, BitDepth::Bpp4, layout, palette, 0x10);
+        assert_eq!(report.inexact_pixel_count, 0);
+        decoded_bytes[..bytes.len()].to_vec()
+    }
+
+    #[test]
+    fn transparent_sheets_round_trip_4bpp() {
+        for layout in SheetLayout::ALL {
+            assert_eq!(
+                round_trip(layout, ColorZero::Transparent, &palette()),
+                graphics_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn back_area_color_sheets_round_trip_4bpp() {
+        // Color 0 and color 5 can't be told apart when both are the back area color.
+        let mut palette = palette();
+        palette[0x15] = Color::WHITE;
+        for layout in SheetLayout::ALL {
+            assert_eq!(
+                round_trip(layout, ColorZero::BackAreaColor, &palette),
+                graphics_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn back_area_color_in_the_line_keeps_its_index() {
+        let palette = palette();
+        let mut image = image::RgbaImage::new(8, 8);
+        let [r, g, b, _] = palette[0].into_rgba8();
+        image.put_pixel(0, 0, image::Rgba([r, g, b, 0xFF]));
+        let (bytes, _) = decode(&image, BitDepth::Bpp4, SheetLayout::Linear, &palette, 0x10);
+        assert_eq!(planar::decode_tile(&bytes, BitDepth::Bpp4)[0], 5);
+        // Outside the line, the back area color is color 0.
+        let (bytes, _) = decode(&image, BitDepth::Bpp4, SheetLayout::Linear, &palette, 0x20);
+        assert_eq!(planar::decode_tile(&bytes, BitDepth::Bpp4)[0], 0);
+    }
+
+    #[test]
+    fn reports_pixels_given_the_nearest_color() {
+        let palette = palette();
+        let mut image = image::RgbaImage::new(16, 8);
+        for (x, y) in [(3, 5), (12, 1)] {
+            let [r, g, b, _] = palette[0x12].into_rgba8();
+            image.put_pixel(x, y, image::Rgba([r, g, b.wrapping_add(x as u8), 0xFF]));
+        }
+        let (_, report) = decode(&image, BitDepth::Bpp4, SheetLayout::Linear, &palette, 0x10);
+        assert_eq!(report.tile_count, 2);
+        assert_eq!(report.inexact_pixel_count, 2);
+        assert_eq!(report.worst_pixel, Some((12, 1)));
+        assert!(report.to_string().ends_with(" at 12, 1)"), "{report}");
+    }
+}
@@ -0,0 +1,278 @@
+//! Project files, which record what's open in the editor so that it survives a restart. They're
+//! TOML so that they can be read and diffed by people, and paths in them are relative to the
+//! project file where possible so that a project can be shared along with its graphics.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block_library::BlockLibraryTile,
+    graphics_file::{self, GraphicsFileSource, GraphicsSlot},
+    lz,
+    palette_animation::PaletteAnimation,
+    rom,
+    tilemap::BitDepth,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Project {
+    /// The file the palette is read from.
+    pub palette: PathBuf,
+    /// The palette line that was selected.
+    pub palette_line: usize,
+    /// The group of 4 colors in the palette line that layer 3 tiles were shown in.
+    #[serde(default)]
+    pub palette_group: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub palette_animations: Vec<PaletteAnimation>,
+    #[serde(default)]
+    pub graphics_files: Vec<ProjectGraphicsFile>,
+    #[serde(default)]
+    pub block_library: Vec<BlockLibraryTile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectGraphicsFile {
+    pub path: PathBuf,
+    /// Set if the graphics are one of the GFX files of the SMW ROM at `path`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gfx_number_in_rom: Option<usize>,
+    pub bit_depth: BitDepth,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slot: Option<GraphicsSlot>,
+}
+impl ProjectGraphicsFile {
+    pub fn source(&self) -> GraphicsFileSource {
+        match self.gfx_number_in_rom {
+            Some(gfx_number) => GraphicsFileSource::Rom(self.path.clone(), gfx_number),
+            None => GraphicsFileSource::File(self.path.clone()),
+        }
+    }
+}
+
+/// A project along with the contents of all of its graphics files, read in the order they're
+/// listed in the project. Graphics files that couldn't be read are left out.
+#[derive(Debug)]
+pub struct OpenedProject {
+    pub path: PathBuf,
+    pub project: Project,
+    pub graphics_files: Vec<(ProjectGraphicsFile, Arc<Vec<u8>>, lz::Compression)>,
+}
+
+pub async fn pick_project_to_open() -> Option<PathBuf> {
+    rfd::AsyncFileDialog::new()
+        .set_title("Open project")
+        .add_filter("Project", &["toml"])
+        .pick_file()
+        .await
+        .map(|file_handle| file_handle.path().to_path_buf())
+}
+
+pub async fn pick_project_to_save() -> Option<PathBuf> {
+    rfd::AsyncFileDialog::new()
+        .set_title("Save project as")
+        .add_filter("Project", &["toml"])
+        .set_file_name("project.toml")
+        .save_file()
+        .await
+        .map(|file_handle| file_handle.path().to_path_buf())
+}
+
+pub async fn open(path: PathBuf) -> Option<Arc<OpenedProject>> {
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .inspect_err(|error| println!("Couldn't read {path:?}: {error}"))
+        .ok()?;
+    let project = from_toml(&contents, &path)
+        .inspect_err(|error| println!("Couldn't parse {path:?}: {error}"))
+        .ok()?;
+
+    // ROMs are only read once, however many of their GFX files the project uses.
+    let mut roms: HashMap<PathBuf, Option<Arc<Vec<rom::RomGraphicsFile>>>> = HashMap::new();
+    let mut graphics_files = vec![];
+    for project_graphics_file in project.graphics_files.iter() {
+        let path = project_graphics_file.path.clone();
+        let loaded = match project_graphics_file.gfx_number_in_rom {
+            Some(gfx_number) => {
+                if !roms.contains_key(&path) {
+                    let rom_graphics_files = graphics_file::load_rom(path.clone())
+                        .await
+                        .map(|(_, rom_graphics_files)| rom_graphics_files);
+                    roms.insert(path.clone(), rom_graphics_files);
+                }
+                roms[&path].as_ref().and_then(|rom_graphics_files| {
+                    rom_graphics_files
+                        .iter()
+                        .find(|rom_graphics_file| rom_graphics_file.gfx_number == gfx_number)
+                        .map(|rom_graphics_file| {
+                            (
+                                Arc::new(rom_graphics_file.bytes.clone()),
+                                rom_graphics_file.compression,
+                            )
+                        })
+                })
+            }
+            None => graphics_file::load_file(path)
+                .await
+                .map(|(_, bytes, compression)| (bytes, compression)),
+        };
+        match loaded {
+            Some((bytes, compression)) => {
+                graphics_files.push((project_graphics_file.clone(), bytes, compression))
+            }
+            None => println!(
+                "Leaving {} out of the project",
+                project_graphics_file.source().name()
+            ),
+        }
+    }
+
+    Some(Arc::new(OpenedProject {
+        path,
+        project,
+        graphics_files,
+    }))
+}
+
+pub async fn save(path: PathBuf, project: Project) -> Option<PathBuf> {
+    let contents = to_toml(project, &path)
+        .inspect_err(|error| println!("Couldn't serialize project: {error}"))
+        .ok()?;
+    tokio::fs::write(&path, contents)
+        .await
+        .inspect_err(|error| println!("Couldn't write {path:?}: {error}"))
+        .ok()?;
+    Some(path)
+}
+
+/// Reads a project saved at `path`, making its paths absolute again.
+fn from_toml(contents: &str, path: &Path) -> Result<Project, toml::de::Error> {
+    let mut project: Project = toml::from_str(contents)?;
+    let project_directory = path.parent().unwrap_or(Path::new(""));
+    project.palette = project_directory.join(&project.palette);
+    for project_graphics_file in project.graphics_files.iter_mut() {
+        project_graphics_file.path = project_directory.join(&project_graphics_file.path);
+    }
+    Ok(project)
+}
+
+/// Writes a project to be saved at `path`, with its paths relative to it where possible.
+fn to_toml(mut project: Project, path: &Path) -> Result<String, toml::ser::Error> {
+    let project_directory = path.parent().unwrap_or(Path::new(""));
+    project.palette = relative_to(&project.palette, project_directory);
+    for project_graphics_file in project.graphics_files.iter_mut() {
+        project_graphics_file.path = relative_to(&project_graphics_file.path, project_directory);
+    }
+    toml::to_string_pretty(&project)
+}
+
+/// Paths outside of the project's directory are kept absolute.
+fn relative_to(path: &Path, directory: &Path) -> PathBuf {
+    path.strip_prefix(directory)
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        palette_animation::AnimationStep,
+        tilemap::{Layer, TileInstance},
+    };
+
+    fn project() -> Project {
+        let mut tile_instance = TileInstance {
+            x: 16,
+            y: 8,
+            id: 3,
+            pal: 2,
+            scale: 2,
+            flags: 0,
+        };
+        tile_instance.set_bit_depth(BitDepth::Bpp4);
+        tile_instance.set_flip_x(true);
+        tile_instance.set_layer(Layer::Bg2);
+        Project {
+            palette: PathBuf::from("/hacks/world/palettes/level.mw3"),
+            palette_line: 3,
+            palette_group: 1,
+            palette_animations: vec![PaletteAnimation {
+                color_index: 0x64,
+                steps: vec![AnimationStep {
+                    color: 0x7FFF,
+                    frames: 8,
+                }],
+            }],
+            graphics_files: vec![
+                ProjectGraphicsFile {
+                    path: PathBuf::from("/hacks/world/ExGFX100.bin"),
+                    gfx_number_in_rom: None,
+                    bit_depth: BitDepth::Bpp4,
+                    slot: Some(GraphicsSlot::Bg1),
+                },
+                ProjectGraphicsFile {
+                    path: PathBuf::from("/roms/smw.smc"),
+                    gfx_number_in_rom: Some(0x14),
+                    bit_depth: BitDepth::Bpp3,
+                    slot: None,
+                },
+            ],
+            block_library: vec![BlockLibraryTile {
+                slot: GraphicsSlot::Bg1,
+                tile_instance,
+            }],
+        }
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let path = Path::new("/hacks/world/project.toml");
+        let contents = to_toml(project(), path).unwrap();
+        assert_eq!(from_toml(&contents, path).unwrap(), project());
+    }
+
+    #[test]
+    fn saves_paths_relative_to_the_project_where_possible() {
+        let contents = to_toml(project(), Path::new("/hacks/world/project.toml")).unwrap();
+        let saved: Project = toml::from_str(&contents).unwrap();
+        assert_eq!(saved.palette, PathBuf::from("palettes/level.mw3"));
+        assert_eq!(saved.graphics_files[0].path, PathBuf::from("ExGFX100.bin"));
+        // Outside of the project's directory.
+        assert_eq!(saved.graphics_files[1].path, PathBuf::from("/roms/smw.smc"));
+    }
+
+    #[test]
+    fn opens_projects_moved_along_with_their_files() {
+        let contents = to_toml(project(), Path::new("/hacks/world/project.toml")).unwrap();
+        let moved = from_toml(&contents, Path::new("/backup/world/project.toml")).unwrap();
+        assert_eq!(
+            moved.palette,
+            PathBuf::from("/backup/world/palettes/level.mw3")
+        );
+        assert_eq!(
+            moved.graphics_files[0].path,
+            PathBuf::from("/backup/world/ExGFX100.bin")
+        );
+        assert_eq!(moved.graphics_files[1].path, PathBuf::from("/roms/smw.smc"));
+    }
+
+    #[test]
+    fn reads_projects_without_optional_fields() {
+        let project = from_toml(
+            "palette = \"palette.mw3\"\npalette_line = 2\n",
+            Path::new("/hacks/project.toml"),
+        )
+        .unwrap();
+        assert_eq!(project.palette, PathBuf::from("/hacks/palette.mw3"));
+        assert_eq!(project.palette_group, 0);
+        assert!(project.palette_animations.is_empty());
+        assert!(project.graphics_files.is_empty());
+        assert!(project.block_library.is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+//! Reading SMW's GFX files out of a ROM image.
+
+use crate::{
+    lz::{self, Compression},
+    tilemap::BitDepth,
+};
+
+const COPIER_HEADER_LEN: usize = 0x200;
+/// The internal header sits at the end of the first bank in both LoROM and SA-1 ROMs.
+const INTERNAL_HEADER_ADDRESS: usize = 0x7FC0;
+const MAP_MODE_OFFSET_IN_INTERNAL_HEADER: usize = 0x15;
+const MAP_MODE_SA1: u8 = 0x23;
+
+/// Where SMW keeps the low, high and bank bytes of the pointers to GFX00 through GFX31.
+const GFX_POINTERS_LOW: u32 = 0x00B992;
+const GFX_POINTERS_HIGH: u32 = 0x00B9C4;
+const GFX_POINTERS_BANK: u32 = 0x00B9F6;
+const GFX_POINTER_COUNT: usize = 0x32;
+
+/// GFX32 and GFX33 aren't in the pointer table. In an unmodified ROM they're stored back to back at
+/// the start of bank 08, directly followed by GFX00.
+const VANILLA_GFX32_ADDRESS: u32 = 0x088000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapping {
+    LoRom,
+    /// SA-1 ROMs with the default Super MMC bank setup, as used by the SA-1 Pack.
+    Sa1,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RomError {
+    /// The file is too small to hold an internal header.
+    TooSmall(usize),
+    /// A pointer in the GFX pointer table doesn't point into the ROM.
+    InvalidPointer { gfx_number: usize, address: u32 },
+    /// A GFX file couldn't be decompressed as LZ2 or LZ3.
+    Undecompressable { gfx_number: usize, address: u32 },
+}
+impl std::fmt::Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooSmall(len) => write!(f, "{len:#X} bytes is too small to be an SMW ROM"),
+            Self::InvalidPointer {
+                gfx_number,
+                address,
+            } => write!(
+                f,
+                "GFX{gfx_number:02X} points to ${address:06X}, which is outside the ROM"
+            ),
+            Self::Undecompressable {
+                gfx_number,
+                address,
+            } => write!(
+                f,
+                "GFX{gfx_number:02X} at ${address:06X} is not valid LZ2 or LZ3 data"
+            ),
+        }
+    }
+}
+
+/// One of the GFX files in a ROM, decompressed.
+#[derive(Debug)]
+pub struct RomGraphicsFile {
+    pub gfx_number: usize,
+    pub compression: Compression,
+    pub bytes: Vec<u8>,
+    pub bit_depth: BitDepth,
+}
+
+/// The bit depth SMW decodes a GFX file with. GFX28-2B are layer 3's 2bpp graphics and GFX32 holds
+/// the player's 4bpp graphics. Everything else is in SMW's 3bpp format.
+pub fn bit_depth_of_gfx(gfx_number: usize) -> BitDepth {
+    match gfx_number {
+        0x28..=0x2B => BitDepth::Bpp2,
+        0x32 => BitDepth::Bpp4,
+        _ => BitDepth::Bpp3,
+    }
+}
+
+pub struct Rom {
+    /// The ROM's bytes without any copier header.
+    bytes: Vec<u8>,
+    mapping: Mapping,
+}
+impl Rom {
+    pub fn new(mut bytes: Vec<u8>) -> Result<Self, RomError> {
+        // Copier headers are the only thing that makes a ROM's size not a multiple of a bank.
+        if bytes.len() % 0x8000 == COPIER_HEADER_LEN {
+            bytes.drain(..COPIER_HEADER_LEN);
+        }
+        let map_mode = *bytes
+            .get(INTERNAL_HEADER_ADDRESS + MAP_MODE_OFFSET_IN_INTERNAL_HEADER)
+            .ok_or(RomError::TooSmall(bytes.len()))?;
+        let mapping = if map_mode == MAP_MODE_SA1 {
+            Mapping::Sa1
+        } else {
+            Mapping::LoRom
+        };
+        Ok(Self { bytes, mapping })
+    }
+
+    pub fn mapping(&self) -> Mapping {
+        self.mapping
+    }
+
+    /// Converts a SNES address into an offset into the (unheadered) ROM bytes.
+    pub fn snes_to_pc(&self, address: u32) -> Option<usize> {
+        let bank = (address >> 16) as usize;
+        let address_in_bank = (address & 0xFFFF) as usize;
+        let pc = match (self.mapping, bank) {
+            // Super MMC maps each of these banks to a whole 64KiB of ROM.
+            (Mapping::Sa1, 0xC0..=0xFF) => (bank - 0xC0) << 16 | address_in_bank,
+            // BW-RAM and I-RAM, not ROM.
+            (Mapping::Sa1, 0x40..=0x7F) => return None,
+            (_, 0x7E..=0x7F) => return None,
+            _ if address_in_bank < 0x8000 => return None,
+            // Super MMC's default blocks 0 and 1 are in banks $00-$3F, and blocks 2 and 3 in banks
+            // $80-$BF rather than mirroring them, each in 32KiB pages.
+            (Mapping::Sa1, _) => {
+                let block_page = (bank & 0x3F) + if bank >= 0x80 { 0x40 } else { 0 };
+                block_page * 0x8000 + (address_in_bank - 0x8000)
+            }
+            (Mapping::LoRom, _) => (bank & 0x7F) * 0x8000 + (address_in_bank - 0x8000),
+        };
+        (pc < self.bytes.len()).then_some(pc)
+    }
+
+    fn read_byte(&self, address: u32) -> Option<u8> {
+        self.snes_to_pc(address).map(|pc| self.bytes[pc])
+    }
+
+    /// Follows the GFX pointer table to decompress GFX00 through GFX31, plus GFX32 and GFX33 if
+    /// they're where an unmodified ROM keeps them. Files that can't be read are reported
+    /// individually, so that one bad pointer doesn't stop the rest from loading.
+    pub fn read_graphics_files(&self) -> Vec<Result<RomGraphicsFile, RomError>> {
+        let mut graphics_files = (0..GFX_POINTER_COUNT)
+            .map(|gfx_number| {
+                let address = self
+                    .read_gfx_pointer(gfx_number)
+                    .ok_or(RomError::TooSmall(self.bytes.len()))?;
+                let (compression, bytes, _) = self.decompress_at(gfx_number, address)?;
+                Ok(RomGraphicsFile {
+                    gfx_number,
+                    compression,
+                    bytes,
+                    bit_depth: bit_depth_of_gfx(gfx_number),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        match self.read_vanilla_gfx32_and_gfx33() {
+            Some(files) => graphics_files.extend(files.map(Ok)),
+            None => println!("GFX32 and GFX33 are not in their vanilla location, skipping them"),
+        }
+
+        graphics_files
+    }
+
+    fn read_gfx_pointer(&self, gfx_number: usize) -> Option<u32> {
+        let read_table = |table: u32| self.read_byte(table + gfx_number as u32).map(u32::from);
+        Some(
+            read_table(GFX_POINTERS_BANK)? << 16
+                | read_table(GFX_POINTERS_HIGH)? << 8
+                | read_table(GFX_POINTERS_LOW)?,
+        )
+    }
+
+    fn decompress_at(
+        &self,
+        gfx_number: usize,
+        address: u32,
+    ) -> Result<(Compression, Vec<u8>, usize), RomError> {
+        let pc = self.snes_to_pc(address).ok_or(RomError::InvalidPointer {
+            gfx_number,
+            address,
+        })?;
+        lz::detect_and_decompress_stream(&self.bytes[pc..]).ok_or(RomError::Undecompressable {
+            gfx_number,
+            address,
+        })
+    }
+
+    /// Only trusts the vanilla location if GFX33 ends exactly where the pointer table says GFX00
+    /// starts, since a modified ROM may have put something else there.
+    fn read_vanilla_gfx32_and_gfx33(&self) -> Option<[RomGraphicsFile; 2]> {
+        let gfx32_pc = self.snes_to_pc(VANILLA_GFX32_ADDRESS)?;
+        let (gfx32_compression, gfx32_bytes, gfx32_len) =
+            lz::detect_and_decompress_stream(&self.bytes[gfx32_pc..])?;
+        let gfx33_pc = gfx32_pc + gfx32_len;
+        let (gfx33_compression, gfx33_bytes, gfx33_len) =
+            lz::detect_and_decompress_stream(self.bytes.get(gfx33_pc..)?)?;
+
+        let gfx00_address = self.read_gfx_pointer(0)?;
+        if self.snes_to_pc(gfx00_address)? != gfx33_pc + gfx33_len {
+            return None;
+        }
+
+        Some([
+            RomGraphicsFile {
+                gfx_number: 0x32,
+                compression: gfx32_compression,
+                bytes: gfx32_bytes,
+                bit_depth: bit_depth_of_gfx(0x32),
+            },
+            RomGraphicsFile {
+                gfx_number: 0x33,
+                compression: gfx33_compression,
+                bytes: gfx33_bytes,
+                bit_depth: bit_depth_of_gfx(0x33),
+            },
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ROM of the given size, with the given map mode in its internal header.
+    fn rom_bytes(len: usize, map_mode: u8) -> Vec<u8> {
+        let mut bytes = vec![0; len];
+        bytes[INTERNAL_HEADER_ADDRESS + MAP_MODE_OFFSET_IN_INTERNAL_HEADER] = map_mode;
+        bytes
+    }
+
+    #[test]
+    fn detects_mapping() {
+        let lorom = Rom::new(rom_bytes(0x80000, 0x20)).unwrap();
+        assert_eq!(lorom.mapping(), Mapping::LoRom);
+        let sa1 = Rom::new(rom_bytes(0x80000, MAP_MODE_SA1)).unwrap();
+        assert_eq!(sa1.mapping(), Mapping::Sa1);
+    }
+
+    #[test]
+    fn strips_copier_headers() {
+        let mut bytes = vec![0xFF; COPIER_HEADER_LEN];
+        bytes.extend(rom_bytes(0x80000, MAP_MODE_SA1));
+        bytes[COPIER_HEADER_LEN] = 0x12;
+        let rom = Rom::new(bytes).unwrap();
+        assert_eq!(rom.mapping(), Mapping::Sa1);
+        assert_eq!(rom.bytes.len(), 0x80000);
+        assert_eq!(rom.read_byte(0x008000), Some(0x12));
+    }
+
+    #[test]
+    fn rejects_roms_without_an_internal_header() {
+        assert!(matches!(
+            Rom::new(vec![0; 0x7000]),
+            Err(RomError::TooSmall(0x7000))
+        ));
+    }
+
+    #[test]
+    fn translates_lorom_addresses() {
+        let rom = Rom::new(rom_bytes(0x100000, 0x20)).unwrap();
+        assert_eq!(rom.snes_to_pc(0x008000), Some(0));
+        assert_eq!(rom.snes_to_pc(0x00B992), Some(0x3992));
+        assert_eq!(rom.snes_to_pc(0x088000), Some(0x40000));
+        assert_eq!(rom.snes_to_pc(0x1FFFFF), Some(0xFFFFF));
+        // Banks $80-$FF mirror banks $00-$7F.
+        assert_eq!(rom.snes_to_pc(0x888000), Some(0x40000));
+        // RAM, and the lower half of banks, aren't ROM.
+        assert_eq!(rom.snes_to_pc(0x007FFF), None);
+        assert_eq!(rom.snes_to_pc(0x7E8000), None);
+        // Past the end of the ROM.
+        assert_eq!(rom.snes_to_pc(0x208000), None);
+    }
+
+    #[test]
+    fn translates_sa1_addresses() {
+        let rom = Rom::new(rom_bytes(0x400000, MAP_MODE_SA1)).unwrap();
+        // Blocks 0 and 1 in banks $00-$3F.
+        assert_eq!(rom.snes_to_pc(0x008000), Some(0));
+        assert_eq!(rom.snes_to_pc(0x088000), Some(0x40000));
+        assert_eq!(rom.snes_to_pc(0x208000), Some(0x100000));
+        assert_eq!(rom.snes_to_pc(0x3FFFFF), Some(0x1FFFFF));
+        // Blocks 2 and 3 in banks $80-$BF.
+        assert_eq!(rom.snes_to_pc(0x808000), Some(0x200000));
+        assert_eq!(rom.snes_to_pc(0xA08000), Some(0x300000));
+        assert_eq!(rom.snes_to_pc(0xBFFFFF), Some(0x3FFFFF));
+        // All four blocks in 64KiB banks from $C0 on.
+        assert_eq!(rom.snes_to_pc(0xC01234), Some(0x1234));
+        assert_eq!(rom.snes_to_pc(0xE00000), Some(0x200000));
+        assert_eq!(rom.snes_to_pc(0xFFFFFF), Some(0x3FFFFF));
+        // BW-RAM, I-RAM and the lower half of banks aren't ROM.
+        assert_eq!(rom.snes_to_pc(0x408000), None);
+        assert_eq!(rom.snes_to_pc(0x6F8000), None);
+        assert_eq!(rom.snes_to_pc(0x807FFF), None);
+    }
+
+    /// Points GFX entry `gfx_number` of the pointer table at the given SNES address.
+    fn set_gfx_pointer(bytes: &mut [u8], gfx_number: usize, address: u32) {
+        let rom = Rom::new(bytes.to_vec()).unwrap();
+        for (table, byte) in [
+            (GFX_POINTERS_LOW, address as u8),
+            (GFX_POINTERS_HIGH, (address >> 8) as u8),
+            (GFX_POINTERS_BANK, (address >> 16) as u8),
+        ] {
+            bytes[rom.snes_to_pc(table + gfx_number as u32).unwrap()] = byte;
+        }
+    }
+
+    /// Tiles whose bytes count up, so that every file's bytes are different.
+    fn graphics(tile_count: usize, bit_depth: BitDepth, first_byte: u8) -> Vec<u8> {
+        (0..tile_count * bit_depth.bytes_per_tile())
+            .map(|i| first_byte.wrapping_add((i / 3) as u8))
+            .collect()
+    }
+
+    #[test]
+    fn reads_graphics_from_the_pointer_table() {
+        let mut bytes = rom_bytes(0x100000, 0x20);
+        let gfx00 = graphics(128, BitDepth::Bpp3, 0x10);
+        let gfx28 = graphics(128, BitDepth::Bpp2, 0x80);
+        let gfx00_stream = lz::compress(Compression::Lz2, &gfx00);
+        let gfx28_stream = lz::compress(Compression::Lz2, &gfx28);
+        bytes[0x80000..][..gfx00_stream.len()].copy_from_slice(&gfx00_stream);
+        bytes[0x90000..][..gfx28_stream.len()].copy_from_slice(&gfx28_stream);
+        set_gfx_pointer(&mut bytes, 0x00, 0x108000);
+        set_gfx_pointer(&mut bytes, 0x28, 0x128000);
+        // A bit-reversed repeat, which LZ2 doesn't have and LZ3 can't do before any output.
+        bytes[0x60000..][..4].copy_from_slice(&[0xA0, 0x00, 0x00, 0xFF]);
+        set_gfx_pointer(&mut bytes, 0x01, 0x0C8000);
+
+        let graphics_files = Rom::new(bytes).unwrap().read_graphics_files();
+        // GFX32 and GFX33 aren't in their vanilla place, so they're skipped.
+        assert_eq!(graphics_files.len(), GFX_POINTER_COUNT);
+
+        let gfx00_file = graphics_files[0x00].as_ref().unwrap();
+        assert_eq!(gfx00_file.gfx_number, 0x00);
+        assert_eq!(gfx00_file.compression, Compression::Lz2);
+        assert_eq!(gfx00_file.bytes, gfx00);
+        assert_eq!(gfx00_file.bit_depth, BitDepth::Bpp3);
+
+        let gfx28_file = graphics_files[0x28].as_ref().unwrap();
+        assert_eq!(gfx28_file.bytes, gfx28);
+        assert_eq!(gfx28_file.bit_depth, BitDepth::Bpp2);
+
+        assert_eq!(
+            graphics_files[0x01].as_ref().unwrap_err(),
+            &RomError::Undecompressable {
+                gfx_number: 0x01,
+                address: 0x0C8000
+            }
+        );
+        // The pointers left at zero point at RAM.
+        assert_eq!(
+            graphics_files[0x02].as_ref().unwrap_err(),
+            &RomError::InvalidPointer {
+                gfx_number: 0x02,
+                address: 0
+            }
+        );
+    }
+
+    #[test]
+    fn reads_gfx32_and_gfx33_where_vanilla_keeps_them() {
+        let mut bytes = rom_bytes(0x100000, 0x20);
+        let gfx32 = graphics(64, BitDepth::Bpp4, 0x20);
+        let gfx33 = graphics(32, BitDepth::Bpp3, 0x40);
+        let gfx00 = graphics(128, BitDepth::Bpp3, 0x60);
+        let mut streams = lz::compress(Compression::Lz2, &gfx32);
+        streams.extend(lz::compress(Compression::Lz2, &gfx33));
+        let gfx00_pc = 0x40000 + streams.len();
+        streams.extend(lz::compress(Compression::Lz2, &gfx00));
+        bytes[0x40000..][..streams.len()].copy_from_slice(&streams);
+        set_gfx_pointer(&mut bytes, 0x00, 0x088000 + (gfx00_pc - 0x40000) as u32);
+
+        let graphics_files = Rom::new(bytes).unwrap().read_graphics_files();
+        assert_eq!(graphics_files.len(), GFX_POINTER_COUNT + 2);
+        assert_eq!(graphics_files[0x00].as_ref().unwrap().bytes, gfx00);
+        let gfx32_file = graphics_files[0x32].as_ref().unwrap();
+        assert_eq!(gfx32_file.gfx_number, 0x32);
+        assert_eq!(gfx32_file.bytes, gfx32);
+        assert_eq!(gfx32_file.bit_depth, BitDepth::Bpp4);
+        let gfx33_file = graphics_files[0x33].as_ref().unwrap();
+        assert_eq!(gfx33_file.gfx_number, 0x33);
+        assert_eq!(gfx33_file.bytes, gfx33);
+    }
+}
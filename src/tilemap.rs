@@ -1,7 +1,12 @@
+use std::collections::VecDeque;
+use std::ops::{Deref, Range};
 use std::sync::Arc;
 use std::sync::RwLock;
 
 use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use crate::palette::Palette;
 
 use iced::widget::canvas::Stroke;
 use iced::Color;
@@ -45,19 +50,90 @@ enum PrivateMessage {
     CursorExited,
 }
 
+/// The graphics bytes shared by every tilemap component. Changes are logged along with a version
+/// number, so that each component can copy just the changed bytes to its GPU buffer.
+#[derive(Debug, Default)]
+pub struct GraphicsBytes {
+    bytes: Vec<u8>,
+    version: u64,
+    /// The byte ranges changed by the most recent versions, oldest first.
+    changes: VecDeque<(u64, Range<usize>)>,
+}
+impl GraphicsBytes {
+    /// Components that fall further behind than this re-upload all of the bytes.
+    const MAX_LOGGED_CHANGES: usize = 256;
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Pads the bytes to the given alignment, then appends to them, returning where the appended
+    /// bytes start.
+    pub fn append(&mut self, alignment: usize, bytes: &[u8]) -> usize {
+        let offset = self.bytes.len().next_multiple_of(alignment);
+        self.bytes.resize(offset, 0);
+        self.bytes.extend_from_slice(bytes);
+        self.log_change(0..self.bytes.len());
+        offset
+    }
+
+    pub fn write(&mut self, offset: usize, bytes: &[u8]) {
+        self.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.log_change(offset..offset + bytes.len());
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.log_change(0..0);
+    }
+
+    /// The byte ranges changed after the given version, or None if that version is too old to know.
+    pub fn changes_since(&self, version: u64) -> Option<impl Iterator<Item = Range<usize>> + '_> {
+        let oldest_logged_version = self
+            .changes
+            .front()
+            .map_or(self.version, |(version, _)| *version);
+        (version + 1 >= oldest_logged_version).then(|| {
+            self.changes
+                .iter()
+                .filter(move |(change_version, _)| *change_version > version)
+                .map(|(_, range)| range.clone())
+        })
+    }
+
+    fn log_change(&mut self, range: Range<usize>) {
+        self.version += 1;
+        self.changes.push_back((self.version, range));
+        if self.changes.len() > Self::MAX_LOGGED_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+}
+impl Deref for GraphicsBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
 pub struct Component {
     gfx_program: TilemapProgram,
     overlay: TilemapCanvasOverlay,
 }
 impl Component {
     pub fn new(
-        graphics_bytes: Arc<RwLock<Vec<u8>>>,
+        graphics_bytes: Arc<RwLock<GraphicsBytes>>,
+        palette: Arc<RwLock<Palette>>,
         tile_instances: Arc<Vec<TileInstance>>,
     ) -> Self {
         Self {
             gfx_program: TilemapProgram {
                 graphics_bytes,
+                tiles_in_drawing_order: in_drawing_order(&tile_instances),
                 tile_instances,
+                palette,
+                highlight_priority: false,
                 pipeline: Default::default(),
             },
             overlay: TilemapCanvasOverlay::new(),
@@ -65,15 +141,44 @@ impl Component {
     }
 
     pub fn set_tile_instances(&mut self, tile_instances: Arc<Vec<TileInstance>>) {
+        self.gfx_program.tiles_in_drawing_order = in_drawing_order(&tile_instances);
         self.gfx_program.tile_instances = tile_instances;
+        // A new layout can be shorter, such as a file shown at a higher bit depth, and leave the
+        // brush where there's no tile.
+        if let Some(brush) = self.overlay.brush_tile {
+            if !self
+                .gfx_program
+                .tile_instances
+                .iter()
+                .any(|tile_instance| tile_instance.get_tile_coords() == brush)
+            {
+                self.overlay.brush_tile = None;
+            }
+        }
+        // The tiles under the outlines may have been scaled, or replaced by differently sized ones.
+        self.overlay.tile_hovered_scale = self
+            .overlay
+            .tile_hovered
+            .map_or(1, |tile| self.scale_of_tile_at(tile));
+        self.overlay.brush_tile_scale = self
+            .overlay
+            .brush_tile
+            .map_or(1, |brush| self.scale_of_tile_at(brush));
+        self.overlay.request_redraw();
     }
 
     pub fn get_tile_instances(&self) -> Arc<Vec<TileInstance>> {
         self.gfx_program.tile_instances.clone()
     }
 
+    /// Sets whether high priority tiles are tinted, so that they stand out.
+    pub fn set_highlight_priority(&mut self, highlight_priority: bool) {
+        self.gfx_program.highlight_priority = highlight_priority;
+    }
+
     pub fn set_brush(&mut self, brush: Option<TileCoords>) {
         self.overlay.brush_tile = brush;
+        self.overlay.brush_tile_scale = brush.map_or(1, |brush| self.scale_of_tile_at(brush));
         self.overlay.request_redraw();
     }
 
@@ -85,6 +190,7 @@ impl Component {
         match envelope.0 {
             PrivateMessage::CursorMovedOverTile(tile) => {
                 self.overlay.tile_hovered = Some(tile);
+                self.overlay.tile_hovered_scale = self.scale_of_tile_at(tile);
                 self.overlay.request_redraw();
                 None
             }
@@ -115,10 +221,20 @@ impl Component {
         }
     }
 
-    pub fn view(&self, dimens_in_tiles: Option<TileCoords>) -> Element<Envelope> {
+    /// How many times its normal size the tile at the given coordinates is drawn, or 1 if there's
+    /// no tile there.
+    fn scale_of_tile_at(&self, tile_coords: TileCoords) -> u32 {
+        topmost_tile_instance(&self.gfx_program.tile_instances, |tile_instance| {
+            tile_instance.get_tile_coords() == tile_coords
+        })
+        .map_or(1, TileInstance::get_scale)
+    }
+
+    pub fn view(&self, dimens_in_tiles: Option<TileCoords>) -> Element<'_, Envelope> {
         use iced::widget::*;
 
-        let instance_count = self.gfx_program.tile_instances.len();
+        let tile_instances = self.gfx_program.tile_instances.clone();
+        let instance_count = tile_instances.len();
         let quad_count = instance_count.div_ceil(4);
         let (quad_columns, quad_rows) = if let Some(dimens_in_tiles) = dimens_in_tiles {
             (dimens_in_tiles.0 / 2, dimens_in_tiles.1 / 2)
@@ -126,9 +242,22 @@ impl Component {
             (quad_count.min(8) as u32, quad_count.div_ceil(8) as u32)
         };
         let gfx_pixels_per_quad = 16;
-        let screen_pixels_per_gfx_pixel = 2;
-        let width = (quad_columns * gfx_pixels_per_quad * screen_pixels_per_gfx_pixel) as u16;
-        let height = (quad_rows * gfx_pixels_per_quad * screen_pixels_per_gfx_pixel) as u16;
+        // Scaled tiles can reach past the quads when the size isn't given.
+        let (gfx_width, gfx_height) = tile_instances.iter().fold(
+            (
+                quad_columns * gfx_pixels_per_quad,
+                quad_rows * gfx_pixels_per_quad,
+            ),
+            |(width, height), tile_instance| match dimens_in_tiles {
+                Some(_) => (width, height),
+                None => (
+                    width.max(tile_instance.x + 8 * tile_instance.get_scale()),
+                    height.max(tile_instance.y + 8 * tile_instance.get_scale()),
+                ),
+            },
+        );
+        let width = (gfx_width * SCREEN_PIXELS_PER_GFX_PIXEL) as u16;
+        let height = (gfx_height * SCREEN_PIXELS_PER_GFX_PIXEL) as u16;
 
         mouse_area(stack!(
             shader_element(&self.gfx_program)
@@ -139,21 +268,56 @@ impl Component {
         .on_press(Envelope(PrivateMessage::LeftButtonPressedInside))
         .on_release(Envelope(PrivateMessage::LeftButtonReleasedInside))
         .on_exit(Envelope(PrivateMessage::CursorExited))
-        .on_move(|point| {
-            Envelope(PrivateMessage::CursorMovedOverTile(TileCoords(
-                (point.x / 16.) as u32,
-                (point.y / 16.) as u32,
+        .on_move(move |point| {
+            let x = (point.x / SCREEN_PIXELS_PER_GFX_PIXEL as f32) as u32;
+            let y = (point.y / SCREEN_PIXELS_PER_GFX_PIXEL as f32) as u32;
+            Envelope(PrivateMessage::CursorMovedOverTile(tile_at_pixel(
+                &tile_instances,
+                x,
+                y,
             )))
         })
         .into()
     }
 }
 
+/// This must match SCREEN_PIXELS_PER_GFX_PIXEL in tilemap_shader.wgsl.
+const SCREEN_PIXELS_PER_GFX_PIXEL: u32 = 2;
+
+/// The last drawn of the tiles that match, which is the one that's seen.
+fn topmost_tile_instance(
+    tile_instances: &[TileInstance],
+    matches: impl Fn(&TileInstance) -> bool,
+) -> Option<&TileInstance> {
+    tile_instances
+        .iter()
+        .enumerate()
+        .filter(|(_, tile_instance)| matches(tile_instance))
+        .max_by_key(|(index, tile_instance)| (tile_instance.mode_1_depth(), *index))
+        .map(|(_, tile_instance)| tile_instance)
+}
+
+/// The coordinates of the tile seen at a pixel, or of the spot in the grid it's in if there's no tile
+/// there. Scaled tiles cover more than their own spot in the grid, so this is whichever tile is
+/// drawn on top at the pixel, which may have its coordinates elsewhere.
+fn tile_at_pixel(tile_instances: &[TileInstance], x: u32, y: u32) -> TileCoords {
+    topmost_tile_instance(tile_instances, |tile_instance| {
+        let size = 8 * tile_instance.get_scale();
+        (tile_instance.x..tile_instance.x + size).contains(&x)
+            && (tile_instance.y..tile_instance.y + size).contains(&y)
+    })
+    .map_or(TileCoords(x / 8, y / 8), TileInstance::get_tile_coords)
+}
+
 type LazyPipelineArc = Arc<RwLock<Option<TilemapShaderPipeline>>>;
 
 struct TilemapProgram {
-    graphics_bytes: Arc<RwLock<Vec<u8>>>,
+    graphics_bytes: Arc<RwLock<GraphicsBytes>>,
     tile_instances: Arc<Vec<TileInstance>>,
+    /// The tile instances sorted once whenever they're set, rather than on every frame.
+    tiles_in_drawing_order: Arc<Vec<TileInstance>>,
+    palette: Arc<RwLock<Palette>>,
+    highlight_priority: bool,
     pipeline: LazyPipelineArc,
 }
 impl shader::Program<Envelope> for TilemapProgram {
@@ -182,7 +346,9 @@ impl shader::Program<Envelope> for TilemapProgram {
     ) -> Self::Primitive {
         TilemapFrameInfo {
             graphics_bytes: self.graphics_bytes.clone(),
-            tile_instances: self.tile_instances.clone(),
+            tile_instances: self.tiles_in_drawing_order.clone(),
+            palette: self.palette.clone(),
+            highlight_priority: self.highlight_priority,
             pipeline: self.pipeline.clone(),
         }
     }
@@ -192,27 +358,44 @@ impl shader::Program<Envelope> for TilemapProgram {
 #[repr(C)]
 pub struct Uniforms {
     resolution: Vec2,
+    /// 1 to tint high priority tiles, 0 not to.
+    highlight_priority: u32,
     padding: u32,
 }
 
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable, Serialize, Deserialize,
+)]
 #[repr(C)]
 pub struct TileInstance {
     // Position (in units of the pixelart's pixels)
     pub x: u32,
     pub y: u32,
 
-    // Which graphic bytes to display
+    // Which graphic bytes to display, as an index into the graphics bytes viewed as an array of
+    // tiles of this instance's bit depth
     pub id: u32,
 
-    // Which palette row to use for colors
+    // Which palette to use for colors. For 3bpp and 4bpp tiles this is a palette row of 16
+    // colors, for 2bpp tiles it's a group of 4 colors, and 8bpp tiles ignore it.
     pub pal: u8,
 
-    // Settings for how to display the graphic
+    // Settings for how to display the graphic. The tile is drawn `scale` times its normal size,
+    // where 0 is taken to mean 1.
     pub scale: u8,
     pub flags: u16,
 }
 impl TileInstance {
+    /// The lowest two bits of the flags hold the bit depth of the tile's graphics.
+    const FLAGS_BIT_DEPTH_MASK: u16 = 0b11;
+    // These values must match the flags in tilemap_shader.wgsl.
+    const FLAG_FLIP_X: u16 = 1 << 2;
+    const FLAG_FLIP_Y: u16 = 1 << 3;
+    const FLAGS_PRIORITY_SHIFT: u16 = 4;
+    const FLAGS_PRIORITY_MASK: u16 = 0b11 << Self::FLAGS_PRIORITY_SHIFT;
+    const FLAGS_LAYER_SHIFT: u16 = 6;
+    const FLAGS_LAYER_MASK: u16 = 0b11 << Self::FLAGS_LAYER_SHIFT;
+
     pub fn get_tile_coords(&self) -> TileCoords {
         TileCoords(self.x / 8, self.y / 8)
     }
@@ -220,13 +403,203 @@ impl TileInstance {
         self.x = tile_coords.0 * 8;
         self.y = tile_coords.1 * 8;
     }
+    pub fn get_scale(&self) -> u32 {
+        self.scale.max(1) as u32
+    }
+    pub fn get_bit_depth(&self) -> BitDepth {
+        BitDepth::from_flags(self.flags & Self::FLAGS_BIT_DEPTH_MASK)
+    }
+    pub fn set_bit_depth(&mut self, bit_depth: BitDepth) {
+        self.flags = (self.flags & !Self::FLAGS_BIT_DEPTH_MASK) | bit_depth.to_flags();
+    }
+    /// Points this tile at the palette colors starting at the given index, taking into account how
+    /// the tile's bit depth indexes into the palette.
+    pub fn set_first_palette_color(&mut self, first_palette_color: usize) {
+        self.pal = self
+            .get_bit_depth()
+            .pal_for_first_palette_color(first_palette_color);
+    }
+    /// Whether the tile is mirrored left to right.
+    pub fn get_flip_x(&self) -> bool {
+        self.flags & Self::FLAG_FLIP_X != 0
+    }
+    pub fn set_flip_x(&mut self, flip_x: bool) {
+        self.set_flag(Self::FLAG_FLIP_X, flip_x);
+    }
+    /// Whether the tile is mirrored top to bottom.
+    pub fn get_flip_y(&self) -> bool {
+        self.flags & Self::FLAG_FLIP_Y != 0
+    }
+    pub fn set_flip_y(&mut self, flip_y: bool) {
+        self.set_flag(Self::FLAG_FLIP_Y, flip_y);
+    }
+    pub fn get_layer(&self) -> Layer {
+        Layer::from_flags((self.flags & Self::FLAGS_LAYER_MASK) >> Self::FLAGS_LAYER_SHIFT)
+    }
+    pub fn set_layer(&mut self, layer: Layer) {
+        self.flags =
+            (self.flags & !Self::FLAGS_LAYER_MASK) | layer.to_flags() << Self::FLAGS_LAYER_SHIFT;
+    }
+    /// Background tiles have a priority of 0 or 1, and sprite tiles of 0 to 3.
+    pub fn get_priority(&self) -> u8 {
+        ((self.flags & Self::FLAGS_PRIORITY_MASK) >> Self::FLAGS_PRIORITY_SHIFT) as u8
+    }
+    pub fn set_priority(&mut self, priority: u8) {
+        self.flags = (self.flags & !Self::FLAGS_PRIORITY_MASK)
+            | (priority.min(self.get_layer().max_priority()) as u16) << Self::FLAGS_PRIORITY_SHIFT;
+    }
+    /// How far in front the tile is drawn in Mode 1, SMW's BG mode, where higher is further in
+    /// front. SMW sets layer 3's priority bit, which brings its high priority tiles in front of
+    /// everything.
+    fn mode_1_depth(&self) -> u8 {
+        match (self.get_layer(), self.get_priority()) {
+            (Layer::Bg3, 0) => 0,
+            (Layer::Sprites, 0) => 1,
+            (Layer::Sprites, 1) => 2,
+            (Layer::Bg2, 0) => 3,
+            (Layer::Bg1, 0) => 4,
+            (Layer::Sprites, 2) => 5,
+            (Layer::Bg2, _) => 6,
+            (Layer::Bg1, _) => 7,
+            (Layer::Sprites, _) => 8,
+            (Layer::Bg3, _) => 9,
+        }
+    }
+    fn set_flag(&mut self, flag: u16, set: bool) {
+        if set {
+            self.flags |= flag;
+        } else {
+            self.flags &= !flag;
+        }
+    }
+}
+
+/// The layers of Mode 1 that a tile can be drawn on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Layer {
+    #[default]
+    Bg1,
+    Bg2,
+    Bg3,
+    Sprites,
+}
+impl Layer {
+    pub const ALL: [Layer; 4] = [Self::Bg1, Self::Bg2, Self::Bg3, Self::Sprites];
+
+    pub fn max_priority(self) -> u8 {
+        match self {
+            Self::Sprites => 3,
+            _ => 1,
+        }
+    }
+
+    // These values must match the LAYER_ constants in tilemap_shader.wgsl.
+    fn to_flags(self) -> u16 {
+        match self {
+            Self::Bg1 => 0,
+            Self::Bg2 => 1,
+            Self::Bg3 => 2,
+            Self::Sprites => 3,
+        }
+    }
+    fn from_flags(flags: u16) -> Self {
+        match flags & 0b11 {
+            0 => Self::Bg1,
+            1 => Self::Bg2,
+            2 => Self::Bg3,
+            _ => Self::Sprites,
+        }
+    }
+}
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Bg1 => "Layer 1",
+            Self::Bg2 => "Layer 2",
+            Self::Bg3 => "Layer 3",
+            Self::Sprites => "Sprites",
+        })
+    }
+}
+
+/// How many bits each pixel of a tile's graphics takes up, in the SNES planar formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BitDepth {
+    /// Used by layer 3.
+    Bpp2,
+    /// SMW's own format: a 2bpp tile followed by a third bitplane of 8 bytes.
+    Bpp3,
+    #[default]
+    Bpp4,
+    /// Used by Mode 3 backgrounds.
+    Bpp8,
+}
+impl BitDepth {
+    pub const ALL: [BitDepth; 4] = [Self::Bpp2, Self::Bpp3, Self::Bpp4, Self::Bpp8];
+
+    pub fn bits_per_pixel(self) -> usize {
+        match self {
+            Self::Bpp2 => 2,
+            Self::Bpp3 => 3,
+            Self::Bpp4 => 4,
+            Self::Bpp8 => 8,
+        }
+    }
+    pub fn bytes_per_tile(self) -> usize {
+        self.bits_per_pixel() * 8 * 8 / 8
+    }
+    /// The `pal` that gives a tile's color 0 the given palette index, rounded down to where one of
+    /// the bit depth's palettes starts. 2bpp tiles pick from groups of 4 colors, so there are 4 of
+    /// their palettes per palette row.
+    pub fn pal_for_first_palette_color(self, first_palette_color: usize) -> u8 {
+        match self {
+            Self::Bpp2 => (first_palette_color / 4) as u8,
+            Self::Bpp3 | Self::Bpp4 => (first_palette_color / 16) as u8,
+            Self::Bpp8 => 0,
+        }
+    }
+    /// The palette index that a tile's color 0 would have, given the tile's `pal`, the same way the
+    /// shader works it out.
+    pub fn first_palette_color(self, pal: u8) -> usize {
+        match self {
+            Self::Bpp2 => pal as usize * 4,
+            Self::Bpp3 | Self::Bpp4 => pal as usize * 16,
+            Self::Bpp8 => 0,
+        }
+    }
+    // These values must match the ones in tilemap_shader.wgsl. 4bpp is 0 so that zeroed flags
+    // give the most common bit depth.
+    fn to_flags(self) -> u16 {
+        match self {
+            Self::Bpp4 => 0,
+            Self::Bpp2 => 1,
+            Self::Bpp3 => 2,
+            Self::Bpp8 => 3,
+        }
+    }
+    fn from_flags(flags: u16) -> Self {
+        match flags {
+            1 => Self::Bpp2,
+            2 => Self::Bpp3,
+            3 => Self::Bpp8,
+            _ => Self::Bpp4,
+        }
+    }
+}
+impl std::fmt::Display for BitDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}bpp", self.bits_per_pixel())
+    }
 }
 
 /// Created every frame, and has the ability to set stuff on the pipeline.
 #[derive(Debug)]
 pub struct TilemapFrameInfo {
-    graphics_bytes: Arc<RwLock<Vec<u8>>>,
+    graphics_bytes: Arc<RwLock<GraphicsBytes>>,
+    /// In drawing order.
     tile_instances: Arc<Vec<TileInstance>>,
+    palette: Arc<RwLock<Palette>>,
+    highlight_priority: bool,
     pipeline: LazyPipelineArc,
 }
 impl shader::Primitive for TilemapFrameInfo {
@@ -261,17 +634,20 @@ impl shader::Primitive for TilemapFrameInfo {
                 device,
                 format,
                 self.graphics_bytes.clone(),
+                &self.palette,
                 self.tile_instances.clone(),
             )
         });
+        pipeline.write_palette_buffer_if_needed(queue, &self.palette);
         pipeline.write_uniforms(
             queue,
             &Uniforms {
                 resolution: Vec2::new(bounds.width, bounds.height),
+                highlight_priority: self.highlight_priority as u32,
                 padding: 0,
             },
         );
-        pipeline.replace_graphics_buffer_if_needed(device, &self.graphics_bytes);
+        pipeline.write_graphics_buffer_if_needed(device, queue, &self.graphics_bytes);
         pipeline.write_tile_instances_if_needed(device, queue, &self.tile_instances);
     }
 
@@ -300,7 +676,11 @@ struct TilemapShaderPipeline {
     pipeline: wgpu::RenderPipeline,
     instance_buffer: wgpu::Buffer,
     palette_buffer: wgpu::Buffer,
+    /// The version of the palette that the palette buffer holds.
+    palette_version: u64,
     graphics_buffer: wgpu::Buffer,
+    /// The version of the graphics bytes that the graphics buffer holds.
+    graphics_version: u64,
     uniform_buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
 }
@@ -308,7 +688,8 @@ impl TilemapShaderPipeline {
     fn new_and_create_wgpu_pipeline(
         device: &wgpu::Device,
         format: wgpu::TextureFormat,
-        graphics_bytes: Arc<RwLock<Vec<u8>>>,
+        graphics_bytes: Arc<RwLock<GraphicsBytes>>,
+        palette: &RwLock<Palette>,
         tile_instances: Arc<Vec<TileInstance>>,
     ) -> Self {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -352,19 +733,12 @@ impl TilemapShaderPipeline {
             multiview: None,
         });
 
-        let mut palette = image::open("assets/palette.png").unwrap().to_rgba32f();
-        palette
-            .as_flat_samples_mut()
-            .samples
-            .iter_mut()
-            .for_each(|c| *c = c.powf(2.2));
-        let palette_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("tilemap palette buffer"),
-            contents: bytemuck::cast_slice(palette.as_flat_samples().samples),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
+        let palette = palette.read().unwrap();
+        let palette_buffer =
+            crate::palette::create_palette_buffer(device, "tilemap palette buffer", &palette);
 
-        let graphics_buffer = create_graphics_buffer(device, &graphics_bytes.read().unwrap());
+        let graphics_bytes = graphics_bytes.read().unwrap();
+        let graphics_buffer = create_graphics_buffer(device, &graphics_bytes);
         let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("tilemap uniform buffer"),
             size: std::mem::size_of::<Uniforms>() as _,
@@ -372,13 +746,13 @@ impl TilemapShaderPipeline {
             mapped_at_creation: false,
         });
         let bind_group = create_bind_group(
-            &device,
+            device,
             &pipeline,
             &palette_buffer,
             &graphics_buffer,
             &uniform_buffer,
         );
-        let instance_buffer = create_instance_buffer(&device, &tile_instances);
+        let instance_buffer = create_instance_buffer(device, &tile_instances);
 
         Self {
             pipeline,
@@ -386,7 +760,9 @@ impl TilemapShaderPipeline {
             uniform_buffer,
             instance_buffer,
             palette_buffer,
+            palette_version: palette.version(),
             graphics_buffer,
+            graphics_version: graphics_bytes.version(),
             bind_group,
         }
     }
@@ -395,24 +771,69 @@ impl TilemapShaderPipeline {
         queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(uniforms));
     }
 
-    fn replace_graphics_buffer_if_needed(
+    fn write_palette_buffer_if_needed(&mut self, queue: &wgpu::Queue, palette: &RwLock<Palette>) {
+        self.palette_version = crate::palette::write_palette_buffer_if_changed(
+            queue,
+            &self.palette_buffer,
+            palette,
+            self.palette_version,
+        );
+    }
+
+    fn write_graphics_buffer_if_needed(
         &mut self,
         device: &wgpu::Device,
-        graphics_bytes_rw: &RwLock<Vec<u8>>,
+        queue: &wgpu::Queue,
+        graphics_bytes_rw: &RwLock<GraphicsBytes>,
     ) {
         let graphics_bytes = graphics_bytes_rw.read().unwrap();
-        // Only updating if size changed for now, since we don't expect the graphics bytes to be edited
-        if self.graphics_buffer.size() != graphics_bytes.len() as _ {
+        // wgpu pads buffers created with contents to a whole number of its copy alignment.
+        let padded_len = (graphics_bytes.len() as wgpu::BufferAddress)
+            .next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT)
+            .max(wgpu::COPY_BUFFER_ALIGNMENT);
+        if self.graphics_buffer.size() != padded_len {
             println!("Graphics buffer size changed, creating new one.");
-            self.graphics_buffer = create_graphics_buffer(&device, &graphics_bytes);
+            self.graphics_buffer = create_graphics_buffer(device, &graphics_bytes);
             self.bind_group = create_bind_group(
-                &device,
+                device,
                 &self.pipeline,
                 &self.palette_buffer,
                 &self.graphics_buffer,
                 &self.uniform_buffer,
             );
+        } else if self.graphics_version != graphics_bytes.version() {
+            match graphics_bytes.changes_since(self.graphics_version) {
+                Some(changed_ranges) => {
+                    for changed_range in changed_ranges {
+                        self.write_graphics_range(queue, &graphics_bytes, changed_range);
+                    }
+                }
+                None => self.write_graphics_range(queue, &graphics_bytes, 0..graphics_bytes.len()),
+            }
+        }
+        self.graphics_version = graphics_bytes.version();
+    }
+
+    /// Copies part of the graphics bytes into the graphics buffer, widening the range to whole
+    /// words as wgpu requires.
+    fn write_graphics_range(
+        &self,
+        queue: &wgpu::Queue,
+        graphics_bytes: &[u8],
+        range: Range<usize>,
+    ) {
+        let alignment = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+        let start = range.start / alignment * alignment;
+        let end = range
+            .end
+            .next_multiple_of(alignment)
+            .min(graphics_bytes.len());
+        if start >= end {
+            return;
         }
+        let mut data = graphics_bytes[start..end].to_vec();
+        data.resize(data.len().next_multiple_of(alignment), 0);
+        queue.write_buffer(&self.graphics_buffer, start as _, &data);
     }
 
     fn write_tile_instances_if_needed(
@@ -425,8 +846,7 @@ impl TilemapShaderPipeline {
             if self.tile_instances.len() != tile_instances.len() {
                 println!("Tile instances buffer size changed, creating new one.");
 
-                self.instance_buffer = create_instance_buffer(&device, &tile_instances);
-                self.tile_instances = tile_instances.clone();
+                self.instance_buffer = create_instance_buffer(device, tile_instances);
             } else {
                 queue.write_buffer(
                     &self.instance_buffer,
@@ -434,6 +854,7 @@ impl TilemapShaderPipeline {
                     bytemuck::cast_slice(tile_instances),
                 );
             }
+            self.tile_instances = tile_instances.clone();
         }
     }
 
@@ -474,7 +895,7 @@ impl TilemapShaderPipeline {
     }
 }
 
-fn create_graphics_buffer(device: &wgpu::Device, graphics_bytes: &Vec<u8>) -> wgpu::Buffer {
+fn create_graphics_buffer(device: &wgpu::Device, graphics_bytes: &[u8]) -> wgpu::Buffer {
     device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("tilemap graphics buffer"),
         contents: graphics_bytes,
@@ -508,10 +929,7 @@ fn create_bind_group(
         ],
     })
 }
-fn create_instance_buffer(
-    device: &wgpu::Device,
-    tile_instances: &Vec<TileInstance>,
-) -> wgpu::Buffer {
+fn create_instance_buffer(device: &wgpu::Device, tile_instances: &[TileInstance]) -> wgpu::Buffer {
     device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
         label: Some("tilemap instance buffer"),
         contents: bytemuck::cast_slice(tile_instances),
@@ -519,19 +937,32 @@ fn create_instance_buffer(
     })
 }
 
+/// Sorts tiles back to front, so that drawing them in order layers them as Mode 1 does. Tiles at
+/// the same depth keep their order.
+fn in_drawing_order(tile_instances: &[TileInstance]) -> Arc<Vec<TileInstance>> {
+    let mut tile_instances = tile_instances.to_vec();
+    tile_instances.sort_by_key(TileInstance::mode_1_depth);
+    Arc::new(tile_instances)
+}
+
 struct TilemapCanvasOverlay {
     pub canvas_cache: canvas::Cache,
     pub tile_hovered: Option<TileCoords>,
+    /// The scale of the tile hovered, so that the outline goes around all of it.
+    pub tile_hovered_scale: u32,
     pub tile_mouse_pressed_on: Option<TileCoords>,
     pub brush_tile: Option<TileCoords>,
+    pub brush_tile_scale: u32,
 }
 impl TilemapCanvasOverlay {
     pub fn new() -> Self {
         Self {
             canvas_cache: canvas::Cache::default(),
             tile_hovered: None,
+            tile_hovered_scale: 1,
             tile_mouse_pressed_on: None,
             brush_tile: None,
+            brush_tile_scale: 1,
         }
     }
     pub fn request_redraw(&mut self) {
@@ -562,7 +993,10 @@ impl<Message> canvas::Program<Message> for TilemapCanvasOverlay {
                         tile_hovered.0 as f32 * 16. - half_stroke_width - 0.5,
                         tile_hovered.1 as f32 * 16. - half_stroke_width - 0.5,
                     ),
-                    Size::new(16. + stroke_width, 16. + stroke_width),
+                    Size::new(
+                        16. * self.tile_hovered_scale as f32 + stroke_width,
+                        16. * self.tile_hovered_scale as f32 + stroke_width,
+                    ),
                     Stroke {
                         // Add a little to the visible stroke width so that even with antialiasing,
                         // the rectangle will not reveal any pixels of the surrounding tiles.
@@ -585,7 +1019,10 @@ impl<Message> canvas::Program<Message> for TilemapCanvasOverlay {
                         brush_tile.0 as f32 * 16. - half_stroke_width - 0.5,
                         brush_tile.1 as f32 * 16. - half_stroke_width - 0.5,
                     ),
-                    Size::new(16. + stroke_width, 16. + stroke_width),
+                    Size::new(
+                        16. * self.brush_tile_scale as f32 + stroke_width,
+                        16. * self.brush_tile_scale as f32 + stroke_width,
+                    ),
                     Stroke {
                         // Add a little to the visible stroke width so that even with antialiasing,
                         // the rectangle will not reveal any pixels of the surrounding tiles.
@@ -598,3 +1035,125 @@ impl<Message> canvas::Program<Message> for TilemapCanvasOverlay {
         })]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(tile_coords: TileCoords, id: u32, layer: Layer, priority: u8) -> TileInstance {
+        let mut tile_instance = TileInstance {
+            x: 0,
+            y: 0,
+            id,
+            pal: 0,
+            scale: 1,
+            flags: 0,
+        };
+        tile_instance.move_to_tile_coords(tile_coords);
+        tile_instance.set_layer(layer);
+        tile_instance.set_priority(priority);
+        tile_instance
+    }
+
+    /// Every layer and priority, from the back to the front of Mode 1 with layer 3's priority bit
+    /// set: BG3L, OBJ0, OBJ1, BG2L, BG1L, OBJ2, BG2H, BG1H, OBJ3, BG3H.
+    const MODE_1_ORDER: [(Layer, u8); 10] = [
+        (Layer::Bg3, 0),
+        (Layer::Sprites, 0),
+        (Layer::Sprites, 1),
+        (Layer::Bg2, 0),
+        (Layer::Bg1, 0),
+        (Layer::Sprites, 2),
+        (Layer::Bg2, 1),
+        (Layer::Bg1, 1),
+        (Layer::Sprites, 3),
+        (Layer::Bg3, 1),
+    ];
+
+    #[test]
+    fn draws_layers_in_mode_1_order() {
+        // Listed front to back, so that sorting has to reverse them.
+        let tile_instances = MODE_1_ORDER
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(id, &(layer, priority))| tile(TileCoords(0, 0), id as u32, layer, priority))
+            .collect::<Vec<_>>();
+        let drawn_ids = in_drawing_order(&tile_instances)
+            .iter()
+            .map(|tile_instance| tile_instance.id)
+            .collect::<Vec<_>>();
+        assert_eq!(drawn_ids, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keeps_the_order_of_tiles_at_the_same_depth() {
+        let tile_instances = [
+            tile(TileCoords(0, 0), 0, Layer::Bg1, 1),
+            tile(TileCoords(1, 0), 1, Layer::Bg2, 0),
+            tile(TileCoords(2, 0), 2, Layer::Bg1, 1),
+            tile(TileCoords(3, 0), 3, Layer::Bg2, 0),
+            tile(TileCoords(4, 0), 4, Layer::Bg1, 1),
+        ];
+        let drawn_ids = in_drawing_order(&tile_instances)
+            .iter()
+            .map(|tile_instance| tile_instance.id)
+            .collect::<Vec<_>>();
+        assert_eq!(drawn_ids, [1, 3, 0, 2, 4]);
+    }
+
+    #[test]
+    fn topmost_tile_is_the_one_in_front() {
+        let at_origin =
+            |tile_instance: &TileInstance| tile_instance.get_tile_coords() == TileCoords(0, 0);
+        // The high priority tile comes first, but is drawn over the low priority one.
+        let tile_instances = [
+            tile(TileCoords(0, 0), 1, Layer::Bg2, 1),
+            tile(TileCoords(0, 0), 2, Layer::Bg1, 0),
+            tile(TileCoords(1, 0), 3, Layer::Bg3, 1),
+        ];
+        assert_eq!(
+            topmost_tile_instance(&tile_instances, at_origin).map(|tile| tile.id),
+            Some(1)
+        );
+        // At the same depth, the tile listed last is drawn last.
+        let tile_instances = [
+            tile(TileCoords(0, 0), 1, Layer::Bg1, 0),
+            tile(TileCoords(0, 0), 2, Layer::Bg1, 0),
+        ];
+        assert_eq!(
+            topmost_tile_instance(&tile_instances, at_origin).map(|tile| tile.id),
+            Some(2)
+        );
+        assert_eq!(topmost_tile_instance(&[], at_origin), None);
+    }
+
+    #[test]
+    fn hits_scaled_tiles_anywhere_they_cover() {
+        let mut scaled = tile(TileCoords(2, 2), 1, Layer::Bg1, 0);
+        scaled.scale = 2;
+        let tile_instances = [scaled];
+        // The lower right quadrant of the 2x tile, which is where tile (3, 3) would be.
+        assert_eq!(tile_at_pixel(&tile_instances, 28, 29), TileCoords(2, 2));
+        assert_eq!(tile_at_pixel(&tile_instances, 31, 31), TileCoords(2, 2));
+        // Just past it.
+        assert_eq!(tile_at_pixel(&tile_instances, 32, 31), TileCoords(4, 3));
+        assert_eq!(tile_at_pixel(&tile_instances, 5, 5), TileCoords(0, 0));
+    }
+
+    #[test]
+    fn hits_whichever_tile_is_in_front_where_a_scaled_one_overlaps() {
+        let mut scaled = tile(TileCoords(0, 0), 1, Layer::Bg1, 0);
+        scaled.scale = 4;
+        let in_front = tile(TileCoords(1, 1), 2, Layer::Bg1, 1);
+        let behind = tile(TileCoords(2, 2), 3, Layer::Bg2, 0);
+        let tile_instances = [scaled, in_front, behind];
+        assert_eq!(tile_at_pixel(&tile_instances, 12, 12), TileCoords(1, 1));
+        assert_eq!(tile_at_pixel(&tile_instances, 20, 20), TileCoords(0, 0));
+        // Covered by the scaled tile only.
+        assert_eq!(tile_at_pixel(&tile_instances, 4, 28), TileCoords(0, 0));
+        // A 1x tile behind the scaled one is hidden by it, so the scaled one is hit.
+        let tile_instances = [scaled, behind];
+        assert_eq!(tile_at_pixel(&tile_instances, 17, 17), TileCoords(0, 0));
+    }
+}
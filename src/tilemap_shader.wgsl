@@ -1,10 +1,11 @@
 
 struct Uniforms {
 	resolution: vec2f,
+	highlight_priority: u32,
 }
 
 @group(0) @binding(0) var<storage> palette: array<vec4f>;
-@group(0) @binding(1) var<storage> graphics: array<vec4u>;
+@group(0) @binding(1) var<storage> graphics: array<u32>;
 @group(0) @binding(2) var<uniform> uniforms: Uniforms;
 
 struct VertexIn {
@@ -13,17 +14,26 @@ struct VertexIn {
 	@location(0) tile_instance: vec4u,
 }
 
+// This must match SCREEN_PIXELS_PER_GFX_PIXEL in tilemap.rs.
+const SCREEN_PIXELS_PER_GFX_PIXEL: f32 = 2.0;
+
 @vertex
 fn vs_main(in: VertexIn) -> VertexOut {
     let uv = vec2f(vec2u((in.vertex_index << 1) & 2, in.vertex_index & 2)) / 2.0;
 
+    // The tile is drawn scale times its normal 8x8 size, where 0 is taken to mean 1.
+    let scale = f32(max((in.tile_instance.w >> 8) & 0xFF, 1u));
+    let size = 8.0 * scale;
+
     // Actual final position of the vertex
     var position = vec2f(uv);
-    position *= 8.0;
+    position *= size;
     position.x += f32(in.tile_instance.x);
     position.y -= f32(in.tile_instance.y);
-    position.y -= 8.0;
-    position *= 4.0;
+    position.y -= size;
+    // Clip space is 2 units across, so this maps gfx pixels to clip space along with the division
+    // by the resolution below.
+    position *= 2.0 * SCREEN_PIXELS_PER_GFX_PIXEL;
     position.x -= uniforms.resolution.x;
     position.y += uniforms.resolution.y;
     position /= uniforms.resolution;
@@ -38,31 +48,84 @@ struct VertexOut {
 	@location(2) pal_scale_flags_flags: u32,
 }
 
+// These values must match BitDepth::to_flags in tilemap.rs.
+const BIT_DEPTH_4BPP: u32 = 0;
+const BIT_DEPTH_2BPP: u32 = 1;
+const BIT_DEPTH_3BPP: u32 = 2;
+const BIT_DEPTH_8BPP: u32 = 3;
+// These values must match the flags in TileInstance in tilemap.rs.
+const FLAG_FLIP_X: u32 = 4;
+const FLAG_FLIP_Y: u32 = 8;
+const FLAGS_PRIORITY_SHIFT: u32 = 4;
+const FLAGS_LAYER_SHIFT: u32 = 6;
+
+// These values must match Layer::to_flags in tilemap.rs.
+const LAYER_SPRITES: u32 = 3;
+
+fn read_graphics_byte(address: u32) -> u32 {
+    return (graphics[address / 4] >> ((address & 3) * 8)) & 0xFF;
+}
+
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4f {
-    let uv = vec2u(u32(in.uv.x * 8), u32((1 - in.uv.y) * 8));
+    var uv = vec2u(u32(in.uv.x * 8), u32((1 - in.uv.y) * 8));
+
+    let pal = in.pal_scale_flags_flags & 0xFF;
+    let flags = in.pal_scale_flags_flags >> 16;
+    let bit_depth = flags & 0x3;
 
-    // Since graphics is an array of vec4u, 2 consecutive items in the array make up the bytes for
-    // 1 tile.
-    let part1 = graphics[in.tile_id * 2 + 0];
-    let part2 = graphics[in.tile_id * 2 + 1];
+    if (flags & FLAG_FLIP_X) != 0 {
+        uv.x = 7 - uv.x;
+    }
+    if (flags & FLAG_FLIP_Y) != 0 {
+        uv.y = 7 - uv.y;
+    }
 
-    let lpart1 = part1[uv.y / 2];
-    let lpart2 = part2[uv.y / 2];
+    var bits_per_pixel: u32 = 4;
+    var pal_offset = pal * 0x10;
+    switch bit_depth {
+        case BIT_DEPTH_2BPP: {
+            bits_per_pixel = 2u;
+            pal_offset = pal * 0x4u;
+        }
+        case BIT_DEPTH_3BPP: {
+            bits_per_pixel = 3u;
+        }
+        case BIT_DEPTH_8BPP: {
+            bits_per_pixel = 8u;
+            pal_offset = 0u;
+        }
+        default: {}
+    }
 
-    let line1 = u32(lpart1 >> ((uv.y & 3) * 16));
-    let line2 = u32(lpart2 >> ((uv.y & 3) * 16));
+    // Each tile is 8 rows of 8 pixels, and each row takes up one byte per bitplane.
+    let tile_address = in.tile_id * bits_per_pixel * 8;
 
+    // Bitplanes are stored in pairs: each pair is 16 bytes, with the two planes of a row
+    // interleaved. SMW's 3bpp format is the exception, where the third plane comes on its own
+    // after the first pair, one byte per row.
     var color_col: u32 = 0;
-    color_col |= ((line1 >> (7 - uv.x)) & 0x1) << 0;
-    color_col |= ((line1 >> (15 - uv.x)) & 0x1) << 1;
-    color_col |= ((line2 >> (7 - uv.x)) & 0x1) << 2;
-    color_col |= ((line2 >> (15 - uv.x)) & 0x1) << 3;
+    for (var plane: u32 = 0; plane < bits_per_pixel; plane++) {
+        var plane_address = tile_address + (plane / 2) * 16 + uv.y * 2 + (plane & 1);
+        if bit_depth == BIT_DEPTH_3BPP && plane == 2 {
+            plane_address = tile_address + 16 + uv.y;
+        }
+        let line = read_graphics_byte(plane_address);
+        color_col |= ((line >> (7 - uv.x)) & 0x1) << plane;
+    }
+
     if color_col == 0 {
 		discard;
-    } else {
-        let pal = in.pal_scale_flags_flags & 0xFF;
-        let pal_offset = u32(pal * 0x10);
-        return palette[color_col + pal_offset];
     }
+
+    var color = palette[color_col + pal_offset];
+    // High priority tiles are the ones in front of low priority layer 1 and 2 tiles: background
+    // tiles with their priority bit set, and sprite tiles with a priority of 2 or 3.
+    let priority = (flags >> FLAGS_PRIORITY_SHIFT) & 0x3;
+    let layer = (flags >> FLAGS_LAYER_SHIFT) & 0x3;
+    let high_priority = priority > select(0u, 1u, layer == LAYER_SPRITES);
+    if uniforms.highlight_priority != 0 && high_priority {
+        color = mix(color, vec4f(1.0, 0.0, 1.0, 1.0), 0.5);
+    }
+    return color;
 }